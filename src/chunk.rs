@@ -1,8 +1,13 @@
-use std::{convert::TryFrom, fmt::Display};
+// the original code here is written long hand, keep it that way
+#![allow(clippy::redundant_field_names)]
+
+use std::{convert::TryFrom, fmt::Display, io::Write};
+use crate::chunk_ref::ChunkRef;
 use crate::chunk_type::ChunkType;
+use crate::compression::{self, CompressionError, CompressionOptions};
 use crate::{Error, Result};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chunk {
     length: u32,
     chunk_type: ChunkType,
@@ -14,31 +19,7 @@ impl TryFrom<&[u8]> for Chunk {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self> {
-        let length = u32::from_be_bytes(<[u8; 4]>::try_from(&value[0..4])?);
-        println!("{:?}", length);
-        let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&value[4..8])?)?;
-        println!("{:?}", chunk_type);
-        let data = value[8..length as usize + 8].to_vec();
-        println!("{:?}", data);
-        let correct_crc = crc::crc32::checksum_ieee(&value[4..length as usize + 8]);
-        let provided_crc = u32::from_be_bytes(<[u8; 4]>::try_from(&value[value.len()-4..value.len()])?);
-        
-        if correct_crc != provided_crc {
-            println!("{:?}", correct_crc);
-            println!("{:?}", provided_crc);
-            return Err(Box::new(ChunkError::InvalidCrc));
-        }
-
-        if length != data.len() as u32 {
-            return Err(Box::new(ChunkError::InvalidDataLength));
-        }
-
-        Ok (Chunk {
-            length,
-            chunk_type,
-            data,
-            crc: correct_crc
-        })
+        Ok(ChunkRef::try_from(value)?.into_owned())
     }
 }
 
@@ -51,13 +32,51 @@ impl Display for Chunk {
 }
 
 impl Chunk {
+    /// Size of everything in a chunk except its data: length, type and crc.
+    pub const METADATA_LENGTH: usize = 12;
+
+    /// Reads the length field at the start of `value` and returns how many bytes
+    /// the whole chunk occupies, checking that they are all there.
+    pub fn encoded_length(value: &[u8]) -> Result<usize> {
+        if value.len() < Self::METADATA_LENGTH {
+            return Err(ChunkError::TruncatedChunk {
+                offset: 0,
+                needed: Self::METADATA_LENGTH,
+                available: value.len()
+            }.into());
+        }
+
+        let data_length = be_u32(&value[0..4]) as usize;
+        let chunk_length = data_length
+            .checked_add(Self::METADATA_LENGTH)
+            .ok_or(ChunkError::InvalidDataLength)?;
+        if value.len() < chunk_length {
+            return Err(ChunkError::TruncatedChunk {
+                offset: 0,
+                needed: chunk_length,
+                available: value.len()
+            }.into());
+        }
+        Ok(chunk_length)
+    }
+
+    // for callers that have already checked the crc
+    pub(crate) fn from_parts(chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> Self {
+        Chunk {
+            length: data.len() as u32,
+            chunk_type,
+            data,
+            crc
+        }
+    }
+
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
-        let crc_data: Vec<u8> = chunk_type.bytes().iter().chain(&data).cloned().collect();
+        let crc = compute_crc(&chunk_type, &data);
         Chunk {
             length: data.len() as u32,
             chunk_type: chunk_type,
             data: data,
-            crc: crc::crc32::checksum_ieee(&crc_data)
+            crc: crc
         }
     }
 
@@ -78,12 +97,59 @@ impl Chunk {
     }
 
     pub fn data_as_string(&self) -> Result<String> {
-        match std::str::from_utf8(&self.data) {
-            Ok(str_data) => Ok(String::from(str_data)),
-            Err(error) => Err(Box::new(error))
+        Ok(String::from(std::str::from_utf8(&self.data)?))
+    }
+
+    /// Inflates the zlib stream this chunk carries, with the default size limit.
+    /// See `decompressed_data_with`.
+    pub fn decompressed_data(&self) -> Result<Vec<u8>> {
+        self.decompressed_data_with(&CompressionOptions::default())
+    }
+
+    /// Inflates the zlib stream inside zTXt, iCCP, compressed iTXt and IDAT
+    /// chunks, skipping the fields in front of it. A single IDAT is only a
+    /// complete stream when the image data isn't split across several of them.
+    pub fn decompressed_data_with(&self, options: &CompressionOptions) -> Result<Vec<u8>> {
+        let start = self.compressed_data_start()?;
+        compression::inflate(&self.data[start..], options.max_decompressed_size)
+    }
+
+    /// Finds where the zlib stream starts, checking the compression method byte
+    /// of zTXt, iCCP and iTXt on the way. Method 0 (zlib) is the only one defined.
+    fn compressed_data_start(&self) -> Result<usize> {
+        let not_compressed = || CompressionError::NotCompressed(self.chunk_type.to_string());
+        let nul_after = |from: usize| self.data.get(from..)?.iter().position(|byte| *byte == 0).map(|nul| from + nul + 1);
+        let method_at = |at: usize| match self.data.get(at) {
+            Some(0) => Ok(at + 1),
+            Some(method) => Err(CompressionError::UnknownMethod(*method)),
+            None => Err(not_compressed())
+        };
+        match &self.chunk_type.bytes() {
+            b"IDAT" => Ok(0),
+            // keyword or profile name, NUL, compression method
+            b"zTXt" | b"iCCP" => Ok(method_at(nul_after(0).ok_or_else(not_compressed)?)?),
+            // keyword, NUL, flag, method, language tag, NUL, translated keyword, NUL
+            b"iTXt" => {
+                let flags = nul_after(0).ok_or_else(not_compressed)?;
+                if self.data.get(flags) != Some(&1) {
+                    return Err(not_compressed().into());
+                }
+                let language = method_at(flags + 1)?;
+                Ok(nul_after(language).and_then(nul_after).ok_or_else(not_compressed)?)
+            }
+            _ => Err(not_compressed().into())
         }
     }
 
+    /// Writes length, type, data and crc straight to `writer`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.length.to_be_bytes())?;
+        writer.write_all(&self.chunk_type.bytes())?;
+        writer.write_all(&self.data)?;
+        writer.write_all(&self.crc.to_be_bytes())?;
+        Ok(())
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         self.length.to_be_bytes()
         .iter()
@@ -95,10 +161,35 @@ impl Chunk {
     }
 }
 
+/// The crc the png spec expects for a chunk, computed over its type and data.
+pub fn compute_crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+    let crc_data: Vec<u8> = chunk_type.bytes().iter().chain(data).cloned().collect();
+    crc::crc32::checksum_ieee(&crc_data)
+}
+
+// callers have already checked that 4 bytes are there
+pub(crate) fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
 #[derive(Debug)]
 pub enum ChunkError {
     InvalidDataLength,
-    InvalidCrc
+    InvalidCrc,
+    // `offset` is where the chunk starts, relative to the parsed buffer
+    TruncatedChunk { offset: usize, needed: usize, available: usize }
+}
+
+impl ChunkError {
+    /// Shifts the offset of a truncation error by `base`, for callers that
+    /// parse a chunk out of the middle of a larger buffer.
+    pub fn at_offset(self, base: usize) -> Self {
+        match self {
+            ChunkError::TruncatedChunk { offset, needed, available } =>
+                ChunkError::TruncatedChunk { offset: offset + base, needed, available },
+            other => other
+        }
+    }
 }
 
 impl std::error::Error for ChunkError {}
@@ -107,7 +198,10 @@ impl Display for ChunkError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ChunkError::InvalidDataLength => write!(f, "Invalid data length"),
-            &ChunkError::InvalidCrc => write!(f, "Incorrect crc")
+            ChunkError::InvalidCrc => write!(f, "Incorrect crc"),
+            ChunkError::TruncatedChunk { offset, needed, available } =>
+                write!(f, "Truncated chunk at byte {}: needs {} bytes, only {} available",
+                offset, needed, available)
         }
     }
 }
@@ -115,6 +209,7 @@ impl Display for ChunkError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
     
     fn testing_chunk() -> Chunk {
         let data_length: u32 = 42;
@@ -228,5 +323,85 @@ mod tests {
         
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    fn test_write_to_matches_as_bytes() {
+        let chunk = testing_chunk();
+        let mut bytes = Vec::new();
+        chunk.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes, chunk.as_bytes());
+    }
+
+    #[test]
+    fn test_truncated_chunk_from_bytes() {
+        let chunk = testing_chunk().as_bytes();
+
+        for end in 0..chunk.len() {
+            assert!(Chunk::try_from(&chunk[..end]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_huge_length_does_not_panic() {
+        #[rustfmt::skip]
+        let chunk_data = vec![
+            255, 255, 255, 255, // length
+            82, 117, 83, 116,   // type
+            1, 2, 3, 4          // crc
+        ];
+
+        match Chunk::try_from(chunk_data.as_ref()) {
+            Err(Error::Chunk(ChunkError::TruncatedChunk { offset, needed, available })) => {
+                assert_eq!(offset, 0);
+                assert_eq!(needed, u32::MAX as usize + 12);
+                assert_eq!(available, 12);
+            }
+            other => panic!("expected truncated chunk, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_trailing_bytes_are_rejected() {
+        let mut chunk_data = testing_chunk().as_bytes();
+        chunk_data.push(0);
+
+        assert!(Chunk::try_from(chunk_data.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_decompressed_data() {
+        let compressed = compression::deflate(b"pixels", 9).unwrap();
+        let idat = Chunk::new(ChunkType::from_str("IDAT").unwrap(), compressed.clone());
+        assert_eq!(idat.decompressed_data().unwrap(), b"pixels");
+
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(&compressed);
+        let ztxt = Chunk::new(ChunkType::from_str("zTXt").unwrap(), data);
+        assert_eq!(ztxt.decompressed_data().unwrap(), b"pixels");
+
+        let mut data = b"Title\0\x01\0en\0Title\0".to_vec();
+        data.extend(&compressed);
+        let itxt = Chunk::new(ChunkType::from_str("iTXt").unwrap(), data);
+        assert_eq!(itxt.decompressed_data().unwrap(), b"pixels");
+    }
+
+    #[test]
+    fn test_decompressed_data_errors() {
+        let uncompressed = Chunk::new(ChunkType::from_str("iTXt").unwrap(), b"Title\0\0\0en\0Title\0text".to_vec());
+        assert!(matches!(uncompressed.decompressed_data(), Err(Error::Compression(CompressionError::NotCompressed(_)))));
+        assert!(testing_chunk().decompressed_data().is_err());
+
+        let idat = Chunk::new(ChunkType::from_str("IDAT").unwrap(), compression::deflate(&[0; 2048], 9).unwrap());
+        let options = CompressionOptions { max_decompressed_size: 1024 };
+        assert!(matches!(idat.decompressed_data_with(&options), Err(Error::Compression(CompressionError::TooLarge(1024)))));
+
+        let mut data = b"Comment\0\x01".to_vec();
+        data.extend(compression::deflate(b"pixels", 9).unwrap());
+        let ztxt = Chunk::new(ChunkType::from_str("zTXt").unwrap(), data);
+        assert!(matches!(ztxt.decompressed_data(), Err(Error::Compression(CompressionError::UnknownMethod(1)))));
+
+        let itxt = Chunk::new(ChunkType::from_str("iTXt").unwrap(), b"Title\0\x01\x07en\0Title\0".to_vec());
+        assert!(matches!(itxt.decompressed_data(), Err(Error::Compression(CompressionError::UnknownMethod(7)))));
+    }
     
 }
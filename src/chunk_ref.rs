@@ -0,0 +1,208 @@
+use std::convert::TryFrom;
+
+use crate::chunk::{be_u32, Chunk, ChunkError};
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, PngError, PngFile};
+use crate::{Error, Result};
+
+/// A read-only chunk that borrows its data from the parsed buffer, so looking
+/// through a file doesn't copy anything. `into_owned` turns it into a `Chunk`.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkRef<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32
+}
+
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = Error;
+
+    fn try_from(value: &'a [u8]) -> Result<Self> {
+        let chunk_length = Chunk::encoded_length(value)?;
+        if value.len() != chunk_length {
+            return Err(ChunkError::InvalidDataLength.into());
+        }
+
+        let chunk_type = ChunkType::try_from([value[4], value[5], value[6], value[7]])?;
+        let data_end = chunk_length - 4;
+        let correct_crc = crc::crc32::checksum_ieee(&value[4..data_end]);
+        let provided_crc = be_u32(&value[data_end..chunk_length]);
+
+        if correct_crc != provided_crc {
+            trace!("crc mismatch type={} expected={:08x} provided={:08x}",
+                chunk_type, correct_crc, provided_crc);
+            return Err(ChunkError::InvalidCrc.into());
+        }
+
+        Ok(ChunkRef {
+            chunk_type,
+            data: &value[8..data_end],
+            crc: correct_crc
+        })
+    }
+}
+
+impl<'a> ChunkRef<'a> {
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    pub fn data_as_string(&self) -> Result<&'a str> {
+        Ok(std::str::from_utf8(self.data)?)
+    }
+
+    /// Copies the data into an owned `Chunk`, reusing the already checked crc.
+    pub fn into_owned(self) -> Chunk {
+        Chunk::from_parts(self.chunk_type, self.data.to_vec(), self.crc)
+    }
+}
+
+/// Iterates over the chunks of a png held in memory (or mapped) as `ChunkRef`s.
+pub struct ChunkRefs<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    finished: bool
+}
+
+impl<'a> ChunkRefs<'a> {
+    /// Checks the png signature of `bytes`, iteration starts at the first chunk.
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < Png::STANDARD_HEADER.len() {
+            return Err(PngError::InvalidLength.into());
+        }
+        if !bytes.starts_with(&Png::STANDARD_HEADER) {
+            return Err(PngError::InvalidHeader.into());
+        }
+
+        Ok(ChunkRefs {
+            bytes,
+            offset: Png::STANDARD_HEADER.len(),
+            finished: false
+        })
+    }
+
+    /// Byte offset of the next chunk from the start of the buffer.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Continues iteration at `offset`, also after an error.
+    pub fn skip_to(&mut self, offset: usize) {
+        self.offset = offset;
+        self.finished = false;
+    }
+
+    fn next_chunk(&mut self) -> Result<ChunkRef<'a>> {
+        let start = self.offset;
+        let remaining = &self.bytes[start..];
+        let chunk_length = Chunk::encoded_length(remaining).map_err(|error| at_offset(error, start))?;
+        trace!("chunk offset={} length={}", start, chunk_length);
+
+        let chunk = ChunkRef::try_from(&remaining[..chunk_length])?;
+        self.offset += chunk_length;
+        Ok(chunk)
+    }
+}
+
+impl<'a> Iterator for ChunkRefs<'a> {
+    type Item = Result<ChunkRef<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished || self.offset >= self.bytes.len() {
+            return None;
+        }
+        let chunk = self.next_chunk();
+        self.finished = chunk.is_err();
+        Some(chunk)
+    }
+}
+
+// turns a truncation offset relative to one chunk into one relative to the whole buffer
+pub(crate) fn at_offset(error: Error, base: usize) -> Error {
+    match error {
+        Error::Chunk(error) => Error::Chunk(error.at_offset(base)),
+        other => other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"I am the first chunk".to_vec()),
+            Chunk::new(ChunkType::from_str("LASt").unwrap(), b"I am the last chunk".to_vec()),
+        ])
+    }
+
+    #[test]
+    fn test_chunk_ref_borrows_data() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"hidden".to_vec());
+        let bytes = chunk.as_bytes();
+        let chunk_ref = ChunkRef::try_from(bytes.as_slice()).unwrap();
+
+        assert_eq!(chunk_ref.length(), 6);
+        assert_eq!(chunk_ref.chunk_type().to_string(), "RuSt");
+        assert_eq!(chunk_ref.data_as_string().unwrap(), "hidden");
+        assert_eq!(chunk_ref.crc(), chunk.crc());
+        assert_eq!(chunk_ref.data().as_ptr(), bytes[8..].as_ptr());
+    }
+
+    #[test]
+    fn test_chunk_ref_into_owned() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"hidden".to_vec());
+        let bytes = chunk.as_bytes();
+        let owned = ChunkRef::try_from(bytes.as_slice()).unwrap().into_owned();
+
+        assert_eq!(owned.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_chunk_ref_invalid_crc() {
+        let mut bytes = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"hidden".to_vec()).as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+
+        assert!(ChunkRef::try_from(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_chunk_refs_iterates_png() {
+        let bytes = testing_png().as_bytes();
+        let chunks: Vec<ChunkRef> = ChunkRefs::new(&bytes).unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].chunk_type().to_string(), "FrSt");
+        assert_eq!(chunks[1].data_as_string().unwrap(), "I am the last chunk");
+    }
+
+    #[test]
+    fn test_chunk_refs_truncated() {
+        let bytes = testing_png().as_bytes();
+        let mut chunks = ChunkRefs::new(&bytes[..bytes.len() - 1]).unwrap();
+
+        assert!(chunks.next().unwrap().is_ok());
+        match chunks.next() {
+            Some(Err(Error::Chunk(ChunkError::TruncatedChunk { offset, .. }))) =>
+                assert_eq!(offset, 8 + 12 + 20),
+            other => panic!("expected truncated chunk, got {:?}", other)
+        }
+        assert!(chunks.next().is_none());
+    }
+}
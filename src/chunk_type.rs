@@ -1,7 +1,10 @@
+// the original code here is written long hand, keep it that way
+#![allow(clippy::manual_range_contains, clippy::match_like_matches_macro)]
+
 use std::{convert::TryFrom, fmt::Display, str::FromStr};
 use crate::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct ChunkType {
     pub data: [u8; 4]
 }
@@ -18,20 +21,19 @@ impl FromStr for ChunkType {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.len() != 4 {
-            return Err(Box::new(ChunkTypeError::InvalidLength(s.len())));
+            return Err(ChunkTypeError::InvalidLength(s.len()).into());
         }
 
         let characters = s.as_bytes();
         for character in characters {
             if !Self::is_valid_byte(*character) {
-                return Err(Box::new(ChunkTypeError::InvalidCharacter(*character)));
+                return Err(ChunkTypeError::InvalidCharacter(*character).into());
             } 
         }
 
-        match <[u8; 4]>::try_from(characters) {
-            Ok(element) => Ok (ChunkType {data: element}),
-            Err(error) => Err(Box::new(error))
-        }
+        let mut data = [0; 4];
+        data.copy_from_slice(characters);
+        Ok(ChunkType { data })
     }  
 }
 
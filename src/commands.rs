@@ -1,49 +1,422 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
 use clap::{App, ArgMatches};
+use ed25519_dalek::{SigningKey, VerifyingKey};
 
-use crate::{get_argument, ArgumentType};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::compression::{self, CompressionOptions};
+use crate::envelope::FileEnvelope;
+use crate::fragment;
+use crate::image::{EncodeOptions, Image};
+use crate::lsb::{self, LsbOptions};
+use crate::mapped::MappedPng;
+use crate::ordering;
+use crate::payload::{self, PackOptions, UnpackOptions, Unpacked};
+use crate::png::{Png, PngError};
+use crate::reader::PngReader;
+use crate::recover::ParseOptions;
+use crate::repair::repair_file;
+use crate::signature;
+use crate::validate::{validate_bytes, Severity};
+use crate::{get_argument, ArgumentType, Error, Result};
  
 pub enum SubCommandType {
     Encode,
     Decode,
     Remove,
-    Print
+    Print,
+    Validate,
+    RepairCrc,
+    Sign,
+    Verify
 }
 
 pub fn get_subcommand(subcommand_type: SubCommandType) -> App<'static> {
     match subcommand_type {
         SubCommandType::Encode => App::new("encode")
                         .about("Encoding message into png")
+                        .after_help("The positional arguments shift with the mode:\n    \
+                            encode <file_path> <chunk_type> <message> [output_file]\n    \
+                            encode <file_path> <message> [output_file] --method lsb\n    \
+                            encode <file_path> <chunk_type> [output_file] --file <file>\n    \
+                            encode <file_path> [output_file] --method lsb --file <file>")
                         .arg(get_argument(ArgumentType::FilePath))
-                        .arg(get_argument(ArgumentType::ChunkType))
-                        .arg(get_argument(ArgumentType::Message))
-                        .arg(get_argument(ArgumentType::OutputFile)),
+                        .arg(get_argument(ArgumentType::ChunkType)
+                            .required(false)
+                            .required_unless_present("method")
+                            .required_if_eq("method", "chunk"))
+                        // checked in encode_operation, --file takes its place
+                        .arg(get_argument(ArgumentType::Message).required(false))
+                        .arg(get_argument(ArgumentType::OutputFile))
+                        .arg(get_argument(ArgumentType::Method))
+                        .arg(get_argument(ArgumentType::Bits))
+                        .arg(get_argument(ArgumentType::ChannelMask))
+                        .arg(get_argument(ArgumentType::Passphrase))
+                        .arg(get_argument(ArgumentType::FragmentSize).conflicts_with("method"))
+                        .arg(get_argument(ArgumentType::File))
+                        .arg(get_argument(ArgumentType::Compress))
+                        .arg(get_argument(ArgumentType::MaxSize))
+                        .arg(get_argument(ArgumentType::HmacKey).conflicts_with("method")),
         SubCommandType::Decode => App::new("decode")
                         .about("Decoding a message from png file")
                         .arg(get_argument(ArgumentType::FilePath))
-                        .arg(get_argument(ArgumentType::ChunkType)),
+                        .arg(get_argument(ArgumentType::ChunkType)
+                            .required(false)
+                            .required_unless_present("method")
+                            .required_if_eq("method", "chunk"))
+                        .arg(get_argument(ArgumentType::Lenient))
+                        .arg(get_argument(ArgumentType::Method))
+                        .arg(get_argument(ArgumentType::Bits))
+                        .arg(get_argument(ArgumentType::ChannelMask))
+                        .arg(get_argument(ArgumentType::Passphrase))
+                        .arg(get_argument(ArgumentType::Fragments).conflicts_with("method"))
+                        .arg(get_argument(ArgumentType::Output))
+                        .arg(get_argument(ArgumentType::MaxSize))
+                        .arg(get_argument(ArgumentType::HmacKey).conflicts_with("method")),
         SubCommandType::Remove => App::new("remove")
-                        .about("Decoding a message from png file")
+                        .about("Removing a chunk from png file")
                         .arg(get_argument(ArgumentType::FilePath))
                         .arg(get_argument(ArgumentType::ChunkType)),
         SubCommandType::Print => App::new("print")
                         .about("print the message")
-                        .arg(get_argument(ArgumentType::FilePath)) 
+                        .arg(get_argument(ArgumentType::FilePath))
+                        .arg(get_argument(ArgumentType::Lenient)),
+        SubCommandType::Validate => App::new("validate")
+                        .about("Checking png file against the specification")
+                        .arg(get_argument(ArgumentType::FilePath))
+                        .arg(get_argument(ArgumentType::Strict)),
+        SubCommandType::RepairCrc => App::new("repair-crc")
+                        .about("Rewriting chunk crcs that don't match their data")
+                        .arg(get_argument(ArgumentType::FilePath)),
+        SubCommandType::Sign => App::new("sign")
+                        .about("Signing the critical chunks of a png with an Ed25519 key")
+                        .arg(get_argument(ArgumentType::FilePath))
+                        .arg(get_argument(ArgumentType::OutputFile))
+                        .arg(get_argument(ArgumentType::Key))
+                        .arg(get_argument(ArgumentType::Include)),
+        SubCommandType::Verify => App::new("verify")
+                        .about("Checking the signature of a png")
+                        .arg(get_argument(ArgumentType::FilePath))
+                        .arg(get_argument(ArgumentType::PublicKey))
+    }
+}
+
+pub fn encode_operation(args: &ArgMatches) -> Result<()> {
+    let file_path = args.value_of("file_path").unwrap();
+    let lsb = args.value_of("method") == Some("lsb");
+
+    // what follows the png depends on the mode: lsb has no chunk type and
+    // --file takes the place of the message, the output file always comes last
+    let mut positionals = ["chunk_type", "message", "output_file"]
+        .iter()
+        .filter_map(|name| args.value_of(name));
+    let chunk_type = if lsb {
+        None
+    } else {
+        Some(ChunkType::from_str(positionals.next().unwrap())?)
+    };
+    let message = match args.value_of("file") {
+        Some(path) => FileEnvelope::read(path)?.to_bytes(),
+        None => positionals
+            .next()
+            .ok_or_else(|| Error::Usage(String::from("no message given, pass one or use --file")))?
+            .as_bytes()
+            .to_vec()
+    };
+    let output_file = positionals.next();
+    if positionals.next().is_some() {
+        return Err(Error::Usage(String::from("too many arguments, the output file comes last")));
+    }
+
+    let payload = seal_payload(args, chunk_type.as_ref(), &message)?;
+    let mut png = read_png(file_path)?;
+    match chunk_type {
+        None => {
+            let mut image = decode_image(args, &png)?;
+            lsb::embed(&mut image, &payload, &lsb_options(args)?)?;
+            png = image.encode_into(&png, &EncodeOptions::default())?;
+        }
+        Some(chunk_type) if args.is_present("fragment_size") => {
+            for fragment in fragment::split(chunk_type, &payload, parse_value(args, "fragment_size")?)? {
+                png.append_chunk(fragment);
+            }
+        }
+        Some(chunk_type) => png.append_chunk(Chunk::new(chunk_type, payload))
+    }
+
+    match output_file {
+        Some(output_file) => write_png(&png, output_file),
+        None => write_atomically(&png, file_path)
     }
 }
 
-// TODO
-pub fn encode_operation(args: &ArgMatches) {
-    println!("{:?}", args);
+pub fn decode_operation(args: &ArgMatches) -> Result<()> {
+    let file_path = args.value_of("file_path").unwrap();
+    let lsb = args.value_of("method") == Some("lsb");
+    let chunk_type = args.value_of("chunk_type").unwrap_or_default();
+
+    // chunk_by_type swallows a malformed type as "not found", report it properly
+    let parsed_chunk_type = if lsb {
+        None
+    } else {
+        Some(ChunkType::from_str(chunk_type)?)
+    };
+
+    let png = if args.is_present("lenient") {
+        read_png_lenient(file_path)?
+    } else {
+        read_png(file_path)?
+    };
+    let payload = if lsb {
+        lsb::extract(&decode_image(args, &png)?, &lsb_options(args)?)?
+    } else if args.is_present("fragments") {
+        fragment::reassemble(&png.chunks_by_type(chunk_type))?
+    } else {
+        // most likely a fragmented message, printing the first piece would be wrong
+        let count = png.chunks_by_type(chunk_type).len();
+        if count > 1 {
+            return Err(Error::Usage(format!("found {} {} chunks, use --fragments to put them back together", count, chunk_type)));
+        }
+        png.chunk_by_type(chunk_type).ok_or(PngError::NotFoundChunk)?.data().to_vec()
+    };
+    let unpacked = open_payload(args, parsed_chunk_type.as_ref(), &payload)?;
+    match (args.value_of("output"), unpacked.is_file) {
+        (Some(output), true) => save_file(&FileEnvelope::from_bytes(&unpacked.message)?, Path::new(output)),
+        (Some(_), false) => Err(Error::Usage(String::from("the hidden data is a message, not a file, leave out --output"))),
+        (None, true) => Err(Error::Usage(String::from("the hidden data is a file, use --output to save it"))),
+        (None, false) => {
+            println!("{}", std::str::from_utf8(&unpacked.message)?);
+            Ok(())
+        }
+    }
 }
 
-pub fn decode_operation(args: &ArgMatches) {
-    println!("{:?}", args);
+// writes an extracted file to `output`, or into it under its original name
+// when `output` is a directory
+fn save_file(envelope: &FileEnvelope, output: &Path) -> Result<()> {
+    let path = if output.is_dir() {
+        // never trust a stored name to stay inside the directory
+        let file_name = Path::new(&envelope.file_name)
+            .file_name()
+            .ok_or_else(|| Error::Usage(String::from("the file has no usable name, give --output a file path")))?;
+        output.join(file_name)
+    } else {
+        output.to_path_buf()
+    };
+    fs::write(&path, &envelope.data)?;
+
+    let digest: String = envelope.digest().iter().map(|byte| format!("{:02x}", byte)).collect();
+    println!("{} ({}, {} bytes, sha256 {}) saved to {}",
+        envelope.file_name, envelope.mime_type, envelope.data.len(), digest, path.display());
+    Ok(())
 }
 
-pub fn remove_operation(args: &ArgMatches) {
-    println!("{:?}", args);
+pub fn remove_operation(args: &ArgMatches) -> Result<()> {
+    let file_path = args.value_of("file_path").unwrap();
+    let chunk_type = args.value_of("chunk_type").unwrap();
+
+    // only the bytes after the removed chunk are rewritten, in a copy that
+    // then replaces the original
+    edit_atomically(file_path, |png| {
+        png.ihdr()?;
+        png.remove_chunk(chunk_type)?;
+        Ok(())
+    })
 }
 
-pub fn print_operation(args: &ArgMatches) {
-    println!("{:?}", args);
-}
\ No newline at end of file
+pub fn print_operation(args: &ArgMatches) -> Result<()> {
+    let file_path = args.value_of("file_path").unwrap();
+
+    if args.is_present("lenient") {
+        read_png_lenient(file_path)?.chunks().iter().for_each(print_chunk);
+        return Ok(());
+    }
+
+    let reader = PngReader::new(BufReader::new(File::open(file_path)?))?;
+    for chunk in reader {
+        print_chunk(&chunk?);
+    }
+    Ok(())
+}
+
+fn print_chunk(chunk: &Chunk) {
+    let chunk_type = chunk.chunk_type();
+    println!("{}\tlength: {}\tcrc: {:08x}\tcritical: {}\tpublic: {}\tsafe to copy: {}",
+            chunk_type, chunk.length(), chunk.crc(),
+            chunk_type.is_critical(), chunk_type.is_public(), chunk_type.is_safe_to_copy());
+}
+
+pub fn validate_operation(args: &ArgMatches) -> Result<()> {
+    let file_path = args.value_of("file_path").unwrap();
+    let strict = args.is_present("strict");
+
+    let findings = validate_bytes(&fs::read(file_path)?);
+    for finding in &findings {
+        println!("{}", finding);
+    }
+
+    let failures = findings
+        .iter()
+        .filter(|finding| strict || finding.severity == Severity::Error)
+        .count();
+    if failures > 0 {
+        return Err(Error::Validation(failures));
+    }
+    Ok(())
+}
+
+pub fn repair_crc_operation(args: &ArgMatches) -> Result<()> {
+    let file_path = args.value_of("file_path").unwrap();
+
+    let repairs = repair_file(file_path)?;
+    for repair in &repairs {
+        println!("fixed {}", repair);
+    }
+    println!("{} chunk(s) fixed", repairs.len());
+    Ok(())
+}
+
+pub fn sign_operation(args: &ArgMatches) -> Result<()> {
+    let file_path = args.value_of("file_path").unwrap();
+    let key = SigningKey::from_bytes(&signature::read_key_file(args.value_of("key").unwrap())?);
+    let ancillary = match args.value_of("include") {
+        Some(list) => signature::parse_chunk_types(list)?,
+        None => Vec::new()
+    };
+
+    let mut png = read_png(file_path)?;
+    signature::sign(&mut png, &key, &ancillary)?;
+    // stdout may be the png itself
+    eprintln!("signed with public key {}", signature::to_hex(key.verifying_key().as_bytes()));
+    match args.value_of("output_file") {
+        Some(output_file) => write_png(&png, output_file),
+        None => write_atomically(&png, file_path)
+    }
+}
+
+pub fn verify_operation(args: &ArgMatches) -> Result<()> {
+    let file_path = args.value_of("file_path").unwrap();
+    let key = VerifyingKey::from_bytes(&signature::read_key_file(args.value_of("public_key").unwrap())?)
+        .map_err(|_| signature::SignatureError::InvalidKey)?;
+
+    let png = read_png(file_path)?;
+    let covered = signature::verify(&png, &key)?;
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let status = if covered.contains(&index) {
+            "signed"
+        } else if ordering::is(chunk.chunk_type(), &signature::CHUNK_TYPE) {
+            "signature"
+        } else {
+            "not signed"
+        };
+        println!("{}\tlength: {}\t{}", chunk.chunk_type(), chunk.length(), status);
+    }
+    println!("signature is valid, {} of {} chunk(s) covered", covered.len(), png.chunks().len());
+    Ok(())
+}
+
+// the bytes that get hidden for `message`, behind the payload header; lsb
+// has no chunk type and can't be tagged, --hmac-key conflicts with --method
+fn seal_payload(args: &ArgMatches, chunk_type: Option<&ChunkType>, message: &[u8]) -> Result<Vec<u8>> {
+    let options = PackOptions {
+        is_file: args.is_present("file"),
+        compression_level: args.is_present("compress").then_some(compression::DEFAULT_LEVEL),
+        passphrase: args.value_of("passphrase"),
+        hmac: hmac_options(args, chunk_type)
+    };
+    payload::pack(message, &options)
+}
+
+// reverses `seal_payload`
+fn open_payload(args: &ArgMatches, chunk_type: Option<&ChunkType>, payload: &[u8]) -> Result<Unpacked> {
+    let options = UnpackOptions {
+        max_size: parse_value(args, "max_size")?,
+        passphrase: args.value_of("passphrase"),
+        hmac: hmac_options(args, chunk_type)
+    };
+    payload::unpack(payload, &options)
+}
+
+fn hmac_options<'a>(args: &'a ArgMatches, chunk_type: Option<&'a ChunkType>) -> Option<(&'a ChunkType, &'a [u8])> {
+    chunk_type.zip(args.value_of("hmac_key").map(str::as_bytes))
+}
+
+fn decode_image(args: &ArgMatches, png: &Png) -> Result<Image> {
+    Image::decode_with(png, &CompressionOptions { max_decompressed_size: parse_value(args, "max_size")? })
+}
+
+fn lsb_options(args: &ArgMatches) -> Result<LsbOptions> {
+    Ok(LsbOptions {
+        bits_per_channel: parse_value(args, "bits")?,
+        channel_mask: parse_value(args, "channel_mask")?
+    })
+}
+
+fn parse_value<T: FromStr>(args: &ArgMatches, name: &str) -> Result<T> {
+    let value = args.value_of(name).unwrap();
+    value.parse().map_err(|_| Error::Usage(format!("invalid value {:?} for --{}", value, name.replace('_', "-"))))
+}
+
+fn read_png(file_path: &str) -> Result<Png> {
+    Png::read_from(BufReader::new(File::open(file_path)?))
+}
+
+// salvages what it can from a damaged file, problems go to stderr
+fn read_png_lenient(file_path: &str) -> Result<Png> {
+    let parsed = Png::parse(&fs::read(file_path)?, &ParseOptions::lenient())?;
+    for finding in &parsed.findings {
+        eprintln!("{}", finding);
+    }
+    Ok(parsed.png)
+}
+
+fn write_png(png: &Png, file_path: &str) -> Result<()> {
+    if file_path == "-" {
+        return png.write_to(io::stdout().lock());
+    }
+    write_to_file(png, Path::new(file_path))
+}
+
+fn write_to_file(png: &Png, path: &Path) -> Result<()> {
+    png.write_to(BufWriter::new(File::create(path)?))
+}
+
+// writes next to the target first and renames over it, so an interrupted
+// write never leaves a half written png behind
+fn write_atomically(png: &Png, file_path: &str) -> Result<()> {
+    let path = Path::new(file_path);
+    let temp_path = temp_path(path);
+
+    let result = write_to_file(png, &temp_path)
+        .and_then(|_| Ok(fs::rename(&temp_path, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// like `write_atomically`, but `edit` changes a mapped copy of the file in
+// place instead of the whole png being written out again
+fn edit_atomically<F: FnOnce(&mut MappedPng) -> Result<()>>(file_path: &str, edit: F) -> Result<()> {
+    let path = Path::new(file_path);
+    let temp_path = temp_path(path);
+
+    let result = fs::copy(path, &temp_path)
+        .map_err(Error::from)
+        .and_then(|_| edit(&mut MappedPng::open(&temp_path)?))
+        .and_then(|_| Ok(fs::rename(&temp_path, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".pngme.tmp");
+    path.with_file_name(temp_name)
+}
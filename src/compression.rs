@@ -0,0 +1,125 @@
+use std::fmt::Display;
+use std::io::{Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::Result;
+
+/// Level used when nothing else is asked for, zlib's own default.
+pub const DEFAULT_LEVEL: u32 = 6;
+pub const MAX_LEVEL: u32 = 9;
+/// Largest payload we inflate by default, a few kilobytes of zlib can expand
+/// to gigabytes so we never trust the stream to stop by itself.
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// How far compressed chunk payloads may expand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionOptions {
+    /// Inflating fails once the output would grow past this many bytes.
+    pub max_decompressed_size: usize
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        CompressionOptions {
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE
+        }
+    }
+}
+
+/// Compresses `data` into a zlib stream.
+pub fn deflate(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    if level > MAX_LEVEL {
+        return Err(CompressionError::InvalidLevel(level).into());
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Inflates a complete zlib stream, giving up as soon as the output passes
+/// `max_size` bytes.
+pub fn inflate(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut decoder = ZlibDecoder::new(data);
+    let mut inflated = Vec::new();
+    // one byte past the limit is enough to tell "exactly at" from "over"
+    decoder
+        .by_ref()
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut inflated)
+        .map_err(|error| CompressionError::InvalidStream(error.to_string()))?;
+
+    if inflated.len() > max_size {
+        return Err(CompressionError::TooLarge(max_size).into());
+    }
+    trace!("inflated {} bytes into {}", decoder.total_in(), inflated.len());
+    Ok(inflated)
+}
+
+#[derive(Debug)]
+pub enum CompressionError {
+    InvalidLevel(u32),
+    InvalidStream(String),
+    TooLarge(usize),
+    NotCompressed(String),
+    UnknownMethod(u8)
+}
+
+impl std::error::Error for CompressionError {}
+
+impl Display for CompressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CompressionError::InvalidLevel(level) =>
+                write!(f, "Compression level must be 0 to {}, found {}", MAX_LEVEL, level),
+            CompressionError::InvalidStream(reason) => write!(f, "Invalid zlib stream: {}", reason),
+            CompressionError::TooLarge(limit) => write!(f, "Decompressed data is larger than {} bytes", limit),
+            CompressionError::NotCompressed(chunk_type) => write!(f, "{} chunk holds no compressed data", chunk_type),
+            CompressionError::UnknownMethod(method) => write!(f, "Unknown compression method {}, only 0 (zlib) is defined", method)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn test_round_trip_every_level() {
+        let data = b"the quick brown fox jumps over the lazy dog ".repeat(20);
+        for level in 0..=MAX_LEVEL {
+            let compressed = deflate(&data, level).unwrap();
+            assert_eq!(inflate(&compressed, data.len()).unwrap(), data, "level {}", level);
+        }
+    }
+
+    #[test]
+    fn test_higher_level_is_smaller() {
+        let data = b"abcabcabcabc".repeat(100);
+        assert!(deflate(&data, 9).unwrap().len() < deflate(&data, 0).unwrap().len());
+    }
+
+    #[test]
+    fn test_invalid_level() {
+        assert!(matches!(deflate(b"data", 10), Err(Error::Compression(CompressionError::InvalidLevel(10)))));
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let bomb = deflate(&vec![0; 1024 * 1024], MAX_LEVEL).unwrap();
+        assert!(bomb.len() < 4096);
+        assert!(matches!(inflate(&bomb, 1024), Err(Error::Compression(CompressionError::TooLarge(1024)))));
+        assert_eq!(inflate(&bomb, 1024 * 1024).unwrap().len(), 1024 * 1024);
+    }
+
+    #[test]
+    fn test_invalid_stream() {
+        assert!(matches!(inflate(b"not zlib at all", 1024), Err(Error::Compression(CompressionError::InvalidStream(_)))));
+
+        let compressed = deflate(b"some text that gets cut off", DEFAULT_LEVEL).unwrap();
+        assert!(inflate(&compressed[..compressed.len() / 2], 1024).is_err());
+    }
+}
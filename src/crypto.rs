@@ -0,0 +1,141 @@
+use std::fmt::Display;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::Result;
+
+/// Format of the sealed header, bumped whenever the layout or kdf settings change.
+pub const VERSION: u8 = 1;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+const KEY_LENGTH: usize = 32;
+/// Version byte, salt and nonce in front of the ciphertext.
+pub const HEADER_LENGTH: usize = 1 + SALT_LENGTH + NONCE_LENGTH;
+
+// argon2id with the OWASP recommended minimum: 19 MiB, 2 passes, 1 lane
+const MEMORY_KIB: u32 = 19 * 1024;
+const ITERATIONS: u32 = 2;
+const PARALLELISM: u32 = 1;
+
+/// Encrypts `plaintext` with a key derived from `passphrase`. The result is
+/// the header (version, random salt, random nonce) followed by the
+/// XChaCha20-Poly1305 ciphertext, the header is authenticated too.
+pub fn seal(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let mut salt = [0; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let mut sealed = Vec::with_capacity(HEADER_LENGTH + plaintext.len() + 16);
+    sealed.push(VERSION);
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&nonce);
+
+    let cipher = cipher(passphrase, &salt)?;
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext, aad: &sealed })
+        .map_err(|_| CryptoError::EncryptionFailed)?;
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+/// Reverses `seal`. A wrong passphrase and a modified header or ciphertext
+/// look the same from here and both fail with `DecryptionFailed`.
+pub fn open(sealed: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if sealed.len() < HEADER_LENGTH {
+        return Err(CryptoError::Truncated.into());
+    }
+    if sealed[0] != VERSION {
+        return Err(CryptoError::UnsupportedVersion(sealed[0]).into());
+    }
+    let (header, ciphertext) = sealed.split_at(HEADER_LENGTH);
+    let salt = &header[1..1 + SALT_LENGTH];
+    let nonce = XNonce::from_slice(&header[1 + SALT_LENGTH..]);
+
+    let cipher = cipher(passphrase, salt)?;
+    let plaintext = cipher
+        .decrypt(nonce, Payload { msg: ciphertext, aad: header })
+        .map_err(|_| CryptoError::DecryptionFailed)?;
+    Ok(plaintext)
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+    let params = Params::new(MEMORY_KIB, ITERATIONS, PARALLELISM, Some(KEY_LENGTH))
+        .map_err(|error| CryptoError::KeyDerivation(error.to_string()))?;
+    let mut key = [0; KEY_LENGTH];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|error| CryptoError::KeyDerivation(error.to_string()))?;
+    trace!("derived key, argon2id m={} t={} p={}", MEMORY_KIB, ITERATIONS, PARALLELISM);
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+#[derive(Debug)]
+pub enum CryptoError {
+    Truncated,
+    UnsupportedVersion(u8),
+    KeyDerivation(String),
+    EncryptionFailed,
+    DecryptionFailed,
+    PassphraseRequired,
+    NotEncrypted
+}
+
+impl std::error::Error for CryptoError {}
+
+impl Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CryptoError::Truncated => write!(f, "Encrypted message is too short to hold its header"),
+            CryptoError::UnsupportedVersion(version) => write!(f, "Unsupported encryption version {}", version),
+            CryptoError::KeyDerivation(reason) => write!(f, "Key derivation failed: {}", reason),
+            CryptoError::EncryptionFailed => write!(f, "Encryption failed"),
+            CryptoError::DecryptionFailed =>
+                write!(f, "Decryption failed: wrong passphrase or the message was tampered with"),
+            CryptoError::PassphraseRequired => write!(f, "Message is encrypted, use --passphrase"),
+            CryptoError::NotEncrypted => write!(f, "Message is not encrypted, leave out --passphrase")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn test_round_trip() {
+        let sealed = seal(b"meet me at noon", "correct horse").unwrap();
+        assert_eq!(sealed[0], VERSION);
+        assert_eq!(sealed.len(), HEADER_LENGTH + 15 + 16);
+        assert!(!sealed.windows(4).any(|window| window == b"meet"));
+        assert_eq!(open(&sealed, "correct horse").unwrap(), b"meet me at noon");
+    }
+
+    #[test]
+    fn test_salt_and_nonce_are_fresh() {
+        assert_ne!(seal(b"same", "same").unwrap(), seal(b"same", "same").unwrap());
+    }
+
+    #[test]
+    fn test_wrong_passphrase_and_tampering() {
+        let sealed = seal(b"meet me at noon", "correct horse").unwrap();
+        assert!(matches!(open(&sealed, "battery staple"), Err(Error::Crypto(CryptoError::DecryptionFailed))));
+
+        for index in [1, HEADER_LENGTH - 1, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 1;
+            assert!(matches!(open(&tampered, "correct horse"), Err(Error::Crypto(CryptoError::DecryptionFailed))));
+        }
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let mut sealed = seal(b"x", "pass").unwrap();
+        assert!(matches!(open(&sealed[..HEADER_LENGTH - 1], "pass"), Err(Error::Crypto(CryptoError::Truncated))));
+        sealed[0] = 2;
+        assert!(matches!(open(&sealed, "pass"), Err(Error::Crypto(CryptoError::UnsupportedVersion(2)))));
+    }
+}
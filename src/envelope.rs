@@ -0,0 +1,237 @@
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::Result;
+
+/// Marks a payload as a wrapped file rather than a plain message.
+pub const MAGIC: [u8; 4] = *b"PMEf";
+pub const VERSION: u8 = 1;
+const DIGEST_LENGTH: usize = 32;
+
+/// A file carried inside a png together with what is needed to restore and
+/// check it. Encoded as magic, version, name length (u16) and name, mime
+/// length (u8) and mime type, size (u64), the SHA-256 of the data and the
+/// data itself, integers big endian.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEnvelope {
+    pub file_name: String,
+    pub mime_type: String,
+    pub data: Vec<u8>
+}
+
+impl FileEnvelope {
+    /// Wraps `data`, guessing the mime type from its first bytes and the name.
+    pub fn new(file_name: &str, data: Vec<u8>) -> Result<Self> {
+        if file_name.len() > u16::MAX as usize {
+            return Err(EnvelopeError::FileNameTooLong(file_name.len()).into());
+        }
+        let mime_type = guess_mime_type(file_name, &data).to_string();
+        Ok(FileEnvelope { file_name: file_name.to_string(), mime_type, data })
+    }
+
+    /// Reads the file at `path`, only its last component is kept as the name.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        FileEnvelope::new(&file_name, fs::read(path)?)
+    }
+
+    pub fn digest(&self) -> [u8; DIGEST_LENGTH] {
+        Sha256::digest(&self.data).into()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.data.len() + self.file_name.len() + self.mime_type.len() + 52);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(self.file_name.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(self.file_name.as_bytes());
+        bytes.push(self.mime_type.len() as u8);
+        bytes.extend_from_slice(self.mime_type.as_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(&self.digest());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Parses an envelope and checks the stored size and digest against the data.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        // the payload flags say whether this is a file, the magic only
+        // catches a corrupted or mismatched envelope
+        if !bytes.starts_with(&MAGIC) {
+            return Err(EnvelopeError::NotAnEnvelope.into());
+        }
+        let mut reader = Reader { bytes, offset: MAGIC.len() };
+        let version = reader.take(1)?[0];
+        if version != VERSION {
+            return Err(EnvelopeError::UnsupportedVersion(version).into());
+        }
+        let name_length = u16::from_be_bytes(reader.array()?) as usize;
+        let file_name = String::from(std::str::from_utf8(reader.take(name_length)?)?);
+        let mime_length = reader.take(1)?[0] as usize;
+        let mime_type = String::from(std::str::from_utf8(reader.take(mime_length)?)?);
+        let size = u64::from_be_bytes(reader.array()?);
+        let digest: [u8; DIGEST_LENGTH] = reader.array()?;
+
+        let data = &bytes[reader.offset..];
+        if data.len() as u64 != size {
+            return Err(EnvelopeError::SizeMismatch { expected: size, found: data.len() as u64 }.into());
+        }
+        let envelope = FileEnvelope { file_name, mime_type, data: data.to_vec() };
+        if envelope.digest() != digest {
+            return Err(EnvelopeError::DigestMismatch.into());
+        }
+        Ok(envelope)
+    }
+}
+
+// hands out consecutive slices, failing once the envelope runs out
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        let slice = self.bytes
+            .get(self.offset..self.offset + count)
+            .ok_or(EnvelopeError::Truncated)?;
+        self.offset += count;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+}
+
+/// Best guess at the mime type: well known signatures first, then the file
+/// extension, then text if the data is utf-8.
+pub fn guess_mime_type(file_name: &str, data: &[u8]) -> &'static str {
+    const SIGNATURES: [(&[u8], &str); 9] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x7fELF", "application/x-executable"),
+        (b"\0asm", "application/wasm"),
+        (b"MZ", "application/vnd.microsoft.portable-executable")
+    ];
+    if let Some((_, mime_type)) = SIGNATURES.iter().find(|(signature, _)| data.starts_with(signature)) {
+        return mime_type;
+    }
+
+    let extension = Path::new(file_name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" | "md" | "log" => "text/plain",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "csv" => "text/csv",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "tar" => "application/x-tar",
+        _ if std::str::from_utf8(data).is_ok() => "text/plain",
+        _ => "application/octet-stream"
+    }
+}
+
+#[derive(Debug)]
+pub enum EnvelopeError {
+    NotAnEnvelope,
+    UnsupportedVersion(u8),
+    FileNameTooLong(usize),
+    Truncated,
+    SizeMismatch { expected: u64, found: u64 },
+    DigestMismatch
+}
+
+impl std::error::Error for EnvelopeError {}
+
+impl Display for EnvelopeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EnvelopeError::NotAnEnvelope => write!(f, "Hidden data is a message, not a file"),
+            EnvelopeError::UnsupportedVersion(version) => write!(f, "Unsupported file envelope version {}", version),
+            EnvelopeError::FileNameTooLong(length) => write!(f, "File name of {} bytes is too long", length),
+            EnvelopeError::Truncated => write!(f, "File envelope ends too early"),
+            EnvelopeError::SizeMismatch { expected, found } =>
+                write!(f, "File should be {} bytes, found {}", expected, found),
+            EnvelopeError::DigestMismatch => write!(f, "File is damaged, SHA-256 doesn't match")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn testing_envelope() -> FileEnvelope {
+        FileEnvelope::new("build.bin", vec![0, 159, 146, 150, 255, 1, 2]).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let envelope = testing_envelope();
+        let bytes = envelope.to_bytes();
+
+        assert!(bytes.starts_with(&MAGIC));
+        assert_eq!(envelope.mime_type, "application/octet-stream");
+        assert_eq!(FileEnvelope::from_bytes(&bytes).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_digest() {
+        // sha-256 of the empty string
+        let envelope = FileEnvelope::new("empty", Vec::new()).unwrap();
+        assert_eq!(envelope.digest()[..4], [0xe3, 0xb0, 0xc4, 0x42]);
+    }
+
+    #[test]
+    fn test_damage_is_detected() {
+        let mut bytes = testing_envelope().to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(FileEnvelope::from_bytes(&bytes), Err(Error::Envelope(EnvelopeError::DigestMismatch))));
+
+        bytes.push(0);
+        assert!(matches!(FileEnvelope::from_bytes(&bytes),
+            Err(Error::Envelope(EnvelopeError::SizeMismatch { expected: 7, found: 8 }))));
+
+        assert!(matches!(FileEnvelope::from_bytes(&bytes[..12]), Err(Error::Envelope(EnvelopeError::Truncated))));
+        assert!(matches!(FileEnvelope::from_bytes(b"plain message"), Err(Error::Envelope(EnvelopeError::NotAnEnvelope))));
+    }
+
+    #[test]
+    fn test_guess_mime_type() {
+        assert_eq!(guess_mime_type("x", b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(guess_mime_type("report.PDF", b"%PDF-1.7"), "application/pdf");
+        assert_eq!(guess_mime_type("data.json", b"{}"), "application/json");
+        assert_eq!(guess_mime_type("notes", b"just text"), "text/plain");
+        assert_eq!(guess_mime_type("blob", &[0xff, 0xfe, 0x00]), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_read_keeps_only_file_name() {
+        let path = std::env::temp_dir().join(format!("pngme-envelope-{}.txt", std::process::id()));
+        fs::write(&path, b"attached").unwrap();
+        let envelope = FileEnvelope::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(envelope.file_name, path.file_name().unwrap().to_str().unwrap());
+        assert_eq!(envelope.mime_type, "text/plain");
+        assert_eq!(envelope.data, b"attached");
+    }
+}
@@ -0,0 +1,301 @@
+use std::fmt::Display;
+
+use crate::chunk::ChunkError;
+use crate::chunk_type::ChunkTypeError;
+use crate::compression::CompressionError;
+use crate::crypto::CryptoError;
+use crate::envelope::EnvelopeError;
+use crate::fragment::FragmentError;
+use crate::ihdr::IhdrError;
+use crate::image::ImageError;
+use crate::integrity::HmacError;
+use crate::lsb::LsbError;
+use crate::payload::PayloadError;
+use crate::png::PngError;
+use crate::signature::SignatureError;
+use crate::text::TextError;
+
+/// Every error the crate can produce. Each variant has its own process exit
+/// code so scripts can tell failures apart without parsing stderr:
+///
+/// | code | variant     | meaning                                         |
+/// |------|-------------|-------------------------------------------------|
+/// | 0    |             | success                                         |
+/// | 2    | `Usage`     | bad command line (clap uses 2 for its own too)  |
+/// | 3    | `Io`        | file could not be read or written               |
+/// | 4    | `Png`       | not a png, or the requested chunk is missing    |
+/// | 5    | `Chunk`     | a chunk is malformed (length, crc, truncation)  |
+/// | 6    | `ChunkType` | a chunk type is not 4 ascii letters             |
+/// | 7    | `Utf8`      | chunk data is not valid utf-8                   |
+/// | 8    | `Ihdr`      | the IHDR chunk is missing or invalid            |
+/// | 9    | `Validation`| `validate` found problems (count inside)        |
+/// | 10   | `Text`      | a tEXt, zTXt or iTXt chunk is malformed         |
+/// | 11   | `Compression`| zlib data is corrupt or inflates too far       |
+/// | 12   | `Image`     | the pixel data can't be decoded                 |
+/// | 13   | `Lsb`       | no message in the pixels, or it doesn't fit     |
+/// | 14   | `Crypto`    | wrong passphrase or tampered encrypted message  |
+/// | 15   | `Fragment`  | message fragments are missing or out of order   |
+/// | 16   | `Envelope`  | embedded file is malformed or fails its digest  |
+/// | 17   | `Payload`   | payload header is malformed or over the limit   |
+/// | 18   | `Signature` | png is unsigned or its signature doesn't verify |
+/// | 19   | `Hmac`      | hmac tag of the hidden message doesn't verify   |
+#[derive(Debug)]
+pub enum Error {
+    Usage(String),
+    Io(std::io::Error),
+    Png(PngError),
+    Chunk(ChunkError),
+    ChunkType(ChunkTypeError),
+    Utf8(std::str::Utf8Error),
+    Ihdr(IhdrError),
+    Validation(usize),
+    Text(TextError),
+    Compression(CompressionError),
+    Image(ImageError),
+    Lsb(LsbError),
+    Crypto(CryptoError),
+    Fragment(FragmentError),
+    Envelope(EnvelopeError),
+    Payload(PayloadError),
+    Signature(SignatureError),
+    Hmac(HmacError)
+}
+
+/// Text for `--help`, keep in sync with the table above.
+pub const EXIT_CODES_HELP: &str = "EXIT CODES:
+    0    success
+    2    usage error
+    3    i/o error
+    4    invalid png or chunk not found
+    5    invalid chunk
+    6    invalid chunk type
+    7    chunk data is not utf-8
+    8    missing or invalid IHDR chunk
+    9    validation found problems
+    10   invalid text chunk
+    11   invalid or oversized compressed data
+    12   image data can't be decoded
+    13   pixel message missing, damaged or too large
+    14   wrong passphrase or tampered message
+    15   message fragments missing or out of order
+    16   embedded file damaged or malformed
+    17   payload header malformed or too large
+    18   missing or invalid signature
+    19   hmac tag doesn't verify";
+
+impl Error {
+    /// The process exit code for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => 2,
+            Error::Io(_) => 3,
+            Error::Png(_) => 4,
+            Error::Chunk(_) => 5,
+            Error::ChunkType(_) => 6,
+            Error::Utf8(_) => 7,
+            Error::Ihdr(_) => 8,
+            Error::Validation(_) => 9,
+            Error::Text(_) => 10,
+            Error::Compression(_) => 11,
+            Error::Image(_) => 12,
+            Error::Lsb(_) => 13,
+            Error::Crypto(_) => 14,
+            Error::Fragment(_) => 15,
+            Error::Envelope(_) => 16,
+            Error::Payload(_) => 17,
+            Error::Signature(_) => 18,
+            Error::Hmac(_) => 19
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Usage(_) => None,
+            Error::Io(error) => Some(error),
+            Error::Png(error) => Some(error),
+            Error::Chunk(error) => Some(error),
+            Error::ChunkType(error) => Some(error),
+            Error::Utf8(error) => Some(error),
+            Error::Ihdr(error) => Some(error),
+            Error::Validation(_) => None,
+            Error::Text(error) => Some(error),
+            Error::Compression(error) => Some(error),
+            Error::Image(error) => Some(error),
+            Error::Lsb(error) => Some(error),
+            Error::Crypto(error) => Some(error),
+            Error::Fragment(error) => Some(error),
+            Error::Envelope(error) => Some(error),
+            Error::Payload(error) => Some(error),
+            Error::Signature(error) => Some(error),
+            Error::Hmac(error) => Some(error)
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Usage(message) => write!(f, "{}", message),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Png(error) => write!(f, "{}", error),
+            Error::Chunk(error) => write!(f, "{}", error),
+            Error::ChunkType(error) => write!(f, "{}", error),
+            Error::Utf8(error) => write!(f, "{}", error),
+            Error::Ihdr(error) => write!(f, "{}", error),
+            Error::Validation(count) => write!(f, "png failed validation with {} problem(s)", count),
+            Error::Text(error) => write!(f, "{}", error),
+            Error::Compression(error) => write!(f, "{}", error),
+            Error::Image(error) => write!(f, "{}", error),
+            Error::Lsb(error) => write!(f, "{}", error),
+            Error::Crypto(error) => write!(f, "{}", error),
+            Error::Fragment(error) => write!(f, "{}", error),
+            Error::Envelope(error) => write!(f, "{}", error),
+            Error::Payload(error) => write!(f, "{}", error),
+            Error::Signature(error) => write!(f, "{}", error),
+            Error::Hmac(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<PngError> for Error {
+    fn from(error: PngError) -> Self {
+        Error::Png(error)
+    }
+}
+
+impl From<ChunkError> for Error {
+    fn from(error: ChunkError) -> Self {
+        Error::Chunk(error)
+    }
+}
+
+impl From<ChunkTypeError> for Error {
+    fn from(error: ChunkTypeError) -> Self {
+        Error::ChunkType(error)
+    }
+}
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(error: std::str::Utf8Error) -> Self {
+        Error::Utf8(error)
+    }
+}
+
+impl From<IhdrError> for Error {
+    fn from(error: IhdrError) -> Self {
+        Error::Ihdr(error)
+    }
+}
+
+impl From<TextError> for Error {
+    fn from(error: TextError) -> Self {
+        Error::Text(error)
+    }
+}
+
+impl From<CompressionError> for Error {
+    fn from(error: CompressionError) -> Self {
+        Error::Compression(error)
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(error: ImageError) -> Self {
+        Error::Image(error)
+    }
+}
+
+impl From<LsbError> for Error {
+    fn from(error: LsbError) -> Self {
+        Error::Lsb(error)
+    }
+}
+
+impl From<CryptoError> for Error {
+    fn from(error: CryptoError) -> Self {
+        Error::Crypto(error)
+    }
+}
+
+impl From<FragmentError> for Error {
+    fn from(error: FragmentError) -> Self {
+        Error::Fragment(error)
+    }
+}
+
+impl From<EnvelopeError> for Error {
+    fn from(error: EnvelopeError) -> Self {
+        Error::Envelope(error)
+    }
+}
+
+impl From<PayloadError> for Error {
+    fn from(error: PayloadError) -> Self {
+        Error::Payload(error)
+    }
+}
+
+impl From<SignatureError> for Error {
+    fn from(error: SignatureError) -> Self {
+        Error::Signature(error)
+    }
+}
+
+impl From<HmacError> for Error {
+    fn from(error: HmacError) -> Self {
+        Error::Hmac(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let invalid_utf8 = vec![0xff];
+        let errors = [
+            Error::Usage(String::from("usage")),
+            Error::Io(std::io::Error::new(std::io::ErrorKind::NotFound, "missing")),
+            Error::Png(PngError::InvalidHeader),
+            Error::Chunk(ChunkError::InvalidCrc),
+            Error::ChunkType(ChunkTypeError::InvalidLength(3)),
+            Error::Utf8(std::str::from_utf8(&invalid_utf8).unwrap_err()),
+            Error::Ihdr(IhdrError::MissingIhdr),
+            Error::Validation(1),
+            Error::Text(TextError::MissingSeparator),
+            Error::Compression(CompressionError::TooLarge(1)),
+            Error::Image(ImageError::MissingImageData),
+            Error::Lsb(LsbError::NoPayload),
+            Error::Crypto(CryptoError::DecryptionFailed),
+            Error::Fragment(FragmentError::NoFragments),
+            Error::Envelope(EnvelopeError::DigestMismatch),
+            Error::Payload(PayloadError::Truncated),
+            Error::Signature(SignatureError::Invalid),
+            Error::Hmac(HmacError::Mismatch)
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(|error| error.exit_code()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0));
+    }
+
+    #[test]
+    fn test_error_from_conversions() {
+        let error: Error = PngError::NotFoundChunk.into();
+        assert_eq!(error.exit_code(), 4);
+
+        let error: Error = ChunkTypeError::InvalidCharacter(b'1').into();
+        assert_eq!(error.exit_code(), 6);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::image::ImageError;
+use crate::Result;
+
+/// The five scanline filters of png filter method 0, the number is the byte
+/// written in front of each filtered row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [
+        FilterType::None, FilterType::Sub, FilterType::Up, FilterType::Average, FilterType::Paeth
+    ];
+
+    pub fn from_byte(byte: u8) -> Result<Self> {
+        Self::ALL
+            .get(byte as usize)
+            .copied()
+            .ok_or_else(|| ImageError::InvalidFilterType(byte).into())
+    }
+}
+
+/// Reverses `filter_type` on `row` in place. `previous` is the already
+/// unfiltered row above (all zeros for the first row of a pass) and
+/// `bytes_per_pixel` is rounded up to at least one.
+pub fn unfilter(filter_type: FilterType, row: &mut [u8], previous: &[u8], bytes_per_pixel: usize) {
+    if filter_type == FilterType::None {
+        return;
+    }
+    // bytes to the left are already unfiltered, which is what the prediction needs
+    for i in 0..row.len() {
+        row[i] = row[i].wrapping_add(predict(filter_type, row, previous, i, bytes_per_pixel));
+    }
+}
+
+/// Applies `filter_type` to `row` and appends the filter byte and the filtered
+/// bytes to `out`. `previous` is the unfiltered row above.
+pub fn filter(filter_type: FilterType, row: &[u8], previous: &[u8], bytes_per_pixel: usize, out: &mut Vec<u8>) {
+    out.push(filter_type as u8);
+    out.extend((0..row.len()).map(|i| row[i].wrapping_sub(predict(filter_type, row, previous, i, bytes_per_pixel))));
+}
+
+/// Tries every filter on `row` and appends the one whose output has the
+/// smallest sum of absolute values, read as signed bytes. This is the
+/// heuristic the png spec suggests, it tends to give zlib the longest runs.
+pub fn filter_adaptive(row: &[u8], previous: &[u8], bytes_per_pixel: usize, out: &mut Vec<u8>) -> FilterType {
+    let mut best = (FilterType::None, u64::MAX, Vec::new());
+    let mut candidate = Vec::with_capacity(row.len() + 1);
+    for filter_type in FilterType::ALL {
+        candidate.clear();
+        filter(filter_type, row, previous, bytes_per_pixel, &mut candidate);
+        let cost = candidate[1..].iter().map(|byte| (*byte as i8).unsigned_abs() as u64).sum();
+        if cost < best.1 {
+            best = (filter_type, cost, candidate.clone());
+        }
+    }
+    out.extend_from_slice(&best.2);
+    best.0
+}
+
+// the value `filter_type` predicts for byte `i`, from the bytes left of it in
+// `row` and the bytes of `previous`
+fn predict(filter_type: FilterType, row: &[u8], previous: &[u8], i: usize, bytes_per_pixel: usize) -> u8 {
+    let left = if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 };
+    let upper_left = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+    match filter_type {
+        FilterType::None => 0,
+        FilterType::Sub => left,
+        FilterType::Up => previous[i],
+        FilterType::Average => ((left as u16 + previous[i] as u16) / 2) as u8,
+        FilterType::Paeth => paeth(left, previous[i], upper_left)
+    }
+}
+
+// picks whichever neighbour is closest to left + above - upper left
+fn paeth(left: u8, above: u8, upper_left: u8) -> u8 {
+    let estimate = left as i16 + above as i16 - upper_left as i16;
+    let distance_left = (estimate - left as i16).abs();
+    let distance_above = (estimate - above as i16).abs();
+    let distance_upper_left = (estimate - upper_left as i16).abs();
+    if distance_left <= distance_above && distance_left <= distance_upper_left {
+        left
+    } else if distance_above <= distance_upper_left {
+        above
+    } else {
+        upper_left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_type_from_byte() {
+        assert_eq!(FilterType::from_byte(4).unwrap(), FilterType::Paeth);
+        assert!(FilterType::from_byte(5).is_err());
+    }
+
+    #[test]
+    fn test_unfilter_sub_and_up() {
+        let mut row = [10, 20, 5, 5];
+        unfilter(FilterType::Sub, &mut row, &[0; 4], 2);
+        assert_eq!(row, [10, 20, 15, 25]);
+
+        let mut row = [1, 2, 255, 4];
+        unfilter(FilterType::Up, &mut row, &[1, 1, 2, 1], 1);
+        assert_eq!(row, [2, 3, 1, 5]);
+    }
+
+    #[test]
+    fn test_unfilter_average_and_paeth() {
+        let mut row = [4, 4];
+        unfilter(FilterType::Average, &mut row, &[10, 20], 1);
+        assert_eq!(row, [9, 4 + (9 + 20) / 2]);
+
+        let mut row = [0, 0];
+        unfilter(FilterType::Paeth, &mut row, &[7, 9], 1);
+        assert_eq!(row, [7, 9]);
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let previous = [3, 200, 17, 90, 255, 0];
+        let row = [10, 250, 30, 4, 128, 77];
+        for filter_type in FilterType::ALL {
+            let mut filtered = Vec::new();
+            filter(filter_type, &row, &previous, 2, &mut filtered);
+            assert_eq!(filtered[0], filter_type as u8);
+
+            unfilter(filter_type, &mut filtered[1..], &previous, 2);
+            assert_eq!(filtered[1..], row, "{:?}", filter_type);
+        }
+    }
+
+    #[test]
+    fn test_filter_adaptive_picks_cheapest() {
+        // a steady ramp turns into a run of small constants after Sub
+        let row: Vec<u8> = (0..32).map(|i| i * 7).collect();
+        let mut out = Vec::new();
+        assert_eq!(filter_adaptive(&row, &[0; 32], 1, &mut out), FilterType::Sub);
+        assert_eq!(out.len(), 33);
+
+        // a row equal to the one above is all zeros after Up
+        let mut out = Vec::new();
+        assert_eq!(filter_adaptive(&row, &row, 1, &mut out), FilterType::Up);
+        assert!(out[1..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_paeth() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(5, 5, 5), 5);
+        assert_eq!(paeth(1, 200, 100), 100);
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt::Display;
+
+use crate::chunk::{be_u32, Chunk};
+use crate::chunk_type::ChunkType;
+use crate::Result;
+
+/// Bytes in front of every fragment: sequence number and total count, both
+/// big endian u32s, the sequence number starts at 0.
+pub const HEADER_LENGTH: usize = 8;
+/// Largest fragment that still fits the 2^31 - 1 chunk length limit.
+pub const MAX_FRAGMENT_SIZE: usize = i32::MAX as usize - HEADER_LENGTH;
+
+/// Splits `payload` into chunks of `chunk_type` carrying at most
+/// `fragment_size` payload bytes each. An empty payload still gives one chunk.
+pub fn split(chunk_type: ChunkType, payload: &[u8], fragment_size: usize) -> Result<Vec<Chunk>> {
+    if fragment_size == 0 || fragment_size > MAX_FRAGMENT_SIZE {
+        return Err(FragmentError::InvalidFragmentSize(fragment_size).into());
+    }
+    let total = payload.len().div_ceil(fragment_size).max(1);
+    let total = u32::try_from(total).map_err(|_| FragmentError::TooManyFragments(total))?;
+
+    let mut pieces: Vec<&[u8]> = payload.chunks(fragment_size).collect();
+    if pieces.is_empty() {
+        pieces.push(&[]);
+    }
+    Ok(pieces
+        .into_iter()
+        .enumerate()
+        .map(|(sequence, piece)| {
+            let mut data = Vec::with_capacity(HEADER_LENGTH + piece.len());
+            data.extend_from_slice(&(sequence as u32).to_be_bytes());
+            data.extend_from_slice(&total.to_be_bytes());
+            data.extend_from_slice(piece);
+            Chunk::new(chunk_type, data)
+        })
+        .collect())
+}
+
+/// Puts a payload written by `split` back together. `fragments` must be the
+/// chunks in file order; every sequence number has to be there exactly once,
+/// in order, and all of them must agree on the total.
+pub fn reassemble(fragments: &[&Chunk]) -> Result<Vec<u8>> {
+    let first = fragments.first().ok_or(FragmentError::NoFragments)?;
+    let total = header(first)?.1;
+
+    let mut payload = Vec::new();
+    for (index, fragment) in fragments.iter().enumerate() {
+        let (sequence, fragment_total) = header(fragment)?;
+        if fragment_total != total {
+            return Err(FragmentError::InconsistentTotal { expected: total, found: fragment_total }.into());
+        }
+        if sequence as usize != index {
+            return Err(FragmentError::OutOfOrder { expected: index as u32, found: sequence }.into());
+        }
+        payload.extend_from_slice(&fragment.data()[HEADER_LENGTH..]);
+    }
+    if fragments.len() != total as usize {
+        return Err(FragmentError::Missing { found: fragments.len(), total }.into());
+    }
+    trace!("reassembled {} fragments into {} bytes", total, payload.len());
+    Ok(payload)
+}
+
+fn header(fragment: &Chunk) -> Result<(u32, u32)> {
+    let data = fragment.data();
+    if data.len() < HEADER_LENGTH {
+        return Err(FragmentError::Truncated(data.len()).into());
+    }
+    let (sequence, total) = (be_u32(&data[0..4]), be_u32(&data[4..8]));
+    if total == 0 || sequence >= total {
+        return Err(FragmentError::InvalidHeader { sequence, total }.into());
+    }
+    Ok((sequence, total))
+}
+
+#[derive(Debug)]
+pub enum FragmentError {
+    InvalidFragmentSize(usize),
+    TooManyFragments(usize),
+    NoFragments,
+    Truncated(usize),
+    InvalidHeader { sequence: u32, total: u32 },
+    InconsistentTotal { expected: u32, found: u32 },
+    OutOfOrder { expected: u32, found: u32 },
+    Missing { found: usize, total: u32 }
+}
+
+impl std::error::Error for FragmentError {}
+
+impl Display for FragmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FragmentError::InvalidFragmentSize(size) =>
+                write!(f, "Fragment size must be 1 to {}, found {}", MAX_FRAGMENT_SIZE, size),
+            FragmentError::TooManyFragments(count) => write!(f, "Payload would need {} fragments", count),
+            FragmentError::NoFragments => write!(f, "No fragments found"),
+            FragmentError::Truncated(length) => write!(f, "Fragment of {} bytes is too short for its header", length),
+            FragmentError::InvalidHeader { sequence, total } =>
+                write!(f, "Invalid fragment header, number {} of {}", sequence, total),
+            FragmentError::InconsistentTotal { expected, found } =>
+                write!(f, "Fragments disagree on the total, {} and {}", expected, found),
+            FragmentError::OutOfOrder { expected, found } =>
+                write!(f, "Expected fragment {}, found fragment {}", expected, found),
+            FragmentError::Missing { found, total } => write!(f, "Only {} of {} fragments found", found, total)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use crate::Error;
+
+    fn split_message(payload: &[u8], fragment_size: usize) -> Vec<Chunk> {
+        split(ChunkType::from_str("ruSt").unwrap(), payload, fragment_size).unwrap()
+    }
+
+    fn reassemble_all(fragments: &[Chunk]) -> Result<Vec<u8>> {
+        reassemble(&fragments.iter().collect::<Vec<&Chunk>>())
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let fragments = split_message(b"a message that needs several chunks", 10);
+
+        assert_eq!(fragments.len(), 4);
+        assert_eq!(&fragments[3].data()[..HEADER_LENGTH], &[0, 0, 0, 3, 0, 0, 0, 4]);
+        assert_eq!(fragments[3].data()[HEADER_LENGTH..], *b"hunks");
+        assert_eq!(reassemble_all(&fragments).unwrap(), b"a message that needs several chunks");
+    }
+
+    #[test]
+    fn test_empty_and_exact_payloads() {
+        let fragments = split_message(b"", 10);
+        assert_eq!(fragments.len(), 1);
+        assert!(reassemble_all(&fragments).unwrap().is_empty());
+
+        assert_eq!(split_message(&[7; 20], 10).len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_fragment_size() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        assert!(matches!(split(chunk_type, b"data", 0), Err(Error::Fragment(FragmentError::InvalidFragmentSize(0)))));
+        assert!(split(chunk_type, b"data", MAX_FRAGMENT_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn test_missing_fragment() {
+        let mut fragments = split_message(b"0123456789", 3);
+        fragments.pop();
+        assert!(matches!(reassemble_all(&fragments), Err(Error::Fragment(FragmentError::Missing { found: 3, total: 4 }))));
+
+        fragments.remove(1);
+        assert!(matches!(reassemble_all(&fragments),
+            Err(Error::Fragment(FragmentError::OutOfOrder { expected: 1, found: 2 }))));
+    }
+
+    #[test]
+    fn test_out_of_order_and_mixed_messages() {
+        let mut fragments = split_message(b"0123456789", 5);
+        fragments.swap(0, 1);
+        assert!(matches!(reassemble_all(&fragments), Err(Error::Fragment(FragmentError::OutOfOrder { .. }))));
+
+        let mut fragments = split_message(b"0123456789", 5);
+        fragments.extend(split_message(b"0123456789", 2));
+        assert!(matches!(reassemble_all(&fragments), Err(Error::Fragment(FragmentError::InconsistentTotal { .. }))));
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        assert!(reassemble(&[]).is_err());
+        assert!(reassemble(&[&Chunk::new(chunk_type, vec![0, 0, 0])]).is_err());
+        assert!(matches!(reassemble(&[&Chunk::new(chunk_type, vec![0, 0, 0, 1, 0, 0, 0, 1])]),
+            Err(Error::Fragment(FragmentError::InvalidHeader { sequence: 1, total: 1 }))));
+    }
+}
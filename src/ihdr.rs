@@ -0,0 +1,262 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::{Error, Result};
+
+/// How pixels are stored, the numbers are the values used in the IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(IhdrError::InvalidColorType(value).into())
+        }
+    }
+}
+
+impl ColorType {
+    /// Bit depths the spec allows for this color type.
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16]
+        }
+    }
+
+    /// Number of samples per pixel.
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterlaceMethod {
+    None = 0,
+    Adam7 = 1
+}
+
+/// The image header, always the first chunk of a png.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace_method: InterlaceMethod
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        if chunk.chunk_type().bytes() != Ihdr::CHUNK_TYPE {
+            return Err(IhdrError::NotIhdr(chunk.chunk_type().to_string()).into());
+        }
+        Ihdr::from_data(chunk.data())
+    }
+}
+
+impl Ihdr {
+    pub const CHUNK_TYPE: [u8; 4] = *b"IHDR";
+    pub const DATA_LENGTH: usize = 13;
+    // width and height are limited to 2^31 - 1 by the spec
+    pub const MAX_DIMENSION: u32 = i32::MAX as u32;
+
+    /// Parses and validates the data of an IHDR chunk.
+    pub fn from_data(data: &[u8]) -> Result<Self> {
+        if data.len() != Ihdr::DATA_LENGTH {
+            return Err(IhdrError::InvalidLength(data.len()).into());
+        }
+
+        let ihdr = Ihdr {
+            width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            bit_depth: data[8],
+            color_type: ColorType::try_from(data[9])?,
+            compression_method: data[10],
+            filter_method: data[11],
+            interlace_method: match data[12] {
+                0 => InterlaceMethod::None,
+                1 => InterlaceMethod::Adam7,
+                other => return Err(IhdrError::InvalidInterlaceMethod(other).into())
+            }
+        };
+        ihdr.validate()?;
+        Ok(ihdr)
+    }
+
+    /// Checks the fields against the rules of the png spec.
+    pub fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0
+            || self.width > Self::MAX_DIMENSION || self.height > Self::MAX_DIMENSION {
+            return Err(IhdrError::InvalidDimensions { width: self.width, height: self.height }.into());
+        }
+        if !self.color_type.allowed_bit_depths().contains(&self.bit_depth) {
+            return Err(IhdrError::InvalidBitDepth {
+                color_type: self.color_type as u8,
+                bit_depth: self.bit_depth
+            }.into());
+        }
+        if self.compression_method != 0 {
+            return Err(IhdrError::InvalidCompressionMethod(self.compression_method).into());
+        }
+        if self.filter_method != 0 {
+            return Err(IhdrError::InvalidFilterMethod(self.filter_method).into());
+        }
+        Ok(())
+    }
+
+    /// Builds the IHDR `Chunk` holding these fields.
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = Vec::with_capacity(Self::DATA_LENGTH);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(self.color_type as u8);
+        data.push(self.compression_method);
+        data.push(self.filter_method);
+        data.push(self.interlace_method as u8);
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data)
+    }
+}
+
+#[derive(Debug)]
+pub enum IhdrError {
+    MissingIhdr,
+    NotIhdr(String),
+    InvalidLength(usize),
+    InvalidDimensions { width: u32, height: u32 },
+    InvalidColorType(u8),
+    InvalidBitDepth { color_type: u8, bit_depth: u8 },
+    InvalidCompressionMethod(u8),
+    InvalidFilterMethod(u8),
+    InvalidInterlaceMethod(u8)
+}
+
+impl std::error::Error for IhdrError {}
+
+impl Display for IhdrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IhdrError::MissingIhdr => write!(f, "Png has no chunks, IHDR is missing"),
+            IhdrError::NotIhdr(chunk_type) => write!(f, "First chunk must be IHDR, found {}", chunk_type),
+            IhdrError::InvalidLength(length) => write!(f, "IHDR data must be 13 bytes, found {}", length),
+            IhdrError::InvalidDimensions { width, height } =>
+                write!(f, "Invalid image dimensions {}x{}", width, height),
+            IhdrError::InvalidColorType(color_type) => write!(f, "Invalid color type {}", color_type),
+            IhdrError::InvalidBitDepth { color_type, bit_depth } =>
+                write!(f, "Bit depth {} is not allowed for color type {}", bit_depth, color_type),
+            IhdrError::InvalidCompressionMethod(method) => write!(f, "Unknown compression method {}", method),
+            IhdrError::InvalidFilterMethod(method) => write!(f, "Unknown filter method {}", method),
+            IhdrError::InvalidInterlaceMethod(method) => write!(f, "Unknown interlace method {}", method)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_ihdr() -> Ihdr {
+        Ihdr {
+            width: 50,
+            height: 50,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: InterlaceMethod::None
+        }
+    }
+
+    fn ihdr_chunk(data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_ihdr_from_chunk() {
+        #[rustfmt::skip]
+        let chunk = ihdr_chunk(&[
+            0, 0, 0, 50,    // width
+            0, 0, 0, 50,    // height
+            8, 6, 0, 0, 0   // bit depth, color type, compression, filter, interlace
+        ]);
+
+        assert_eq!(Ihdr::try_from(&chunk).unwrap(), testing_ihdr());
+    }
+
+    #[test]
+    fn test_ihdr_round_trip() {
+        let ihdr = testing_ihdr();
+        assert_eq!(Ihdr::try_from(&ihdr.to_chunk()).unwrap(), ihdr);
+    }
+
+    #[test]
+    fn test_ihdr_wrong_chunk_type() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), testing_ihdr().to_chunk().data().to_vec());
+        assert!(matches!(Ihdr::try_from(&chunk), Err(Error::Ihdr(IhdrError::NotIhdr(_)))));
+    }
+
+    #[test]
+    fn test_ihdr_wrong_length() {
+        let chunk = ihdr_chunk(&[0, 0, 0, 50]);
+        assert!(matches!(Ihdr::try_from(&chunk), Err(Error::Ihdr(IhdrError::InvalidLength(4)))));
+    }
+
+    #[test]
+    fn test_ihdr_bit_depth_combinations() {
+        for (color_type, bit_depth, valid) in [
+            (ColorType::Grayscale, 1, true),
+            (ColorType::Grayscale, 16, true),
+            (ColorType::Indexed, 8, true),
+            (ColorType::Indexed, 16, false),
+            (ColorType::Rgb, 4, false),
+            (ColorType::Rgba, 16, true),
+            (ColorType::GrayscaleAlpha, 2, false),
+            (ColorType::Grayscale, 3, false)
+        ] {
+            let ihdr = Ihdr { color_type, bit_depth, ..testing_ihdr() };
+            assert_eq!(ihdr.validate().is_ok(), valid, "{:?} {}", color_type, bit_depth);
+        }
+    }
+
+    #[test]
+    fn test_ihdr_invalid_fields() {
+        assert!(Ihdr { width: 0, ..testing_ihdr() }.validate().is_err());
+        assert!(Ihdr { height: u32::MAX, ..testing_ihdr() }.validate().is_err());
+        assert!(Ihdr { compression_method: 1, ..testing_ihdr() }.validate().is_err());
+        assert!(Ihdr { filter_method: 1, ..testing_ihdr() }.validate().is_err());
+
+        let mut data = testing_ihdr().to_chunk().data().to_vec();
+        data[9] = 5;
+        assert!(matches!(Ihdr::try_from(&ihdr_chunk(&data)), Err(Error::Ihdr(IhdrError::InvalidColorType(5)))));
+        data[9] = 6;
+        data[12] = 2;
+        assert!(matches!(Ihdr::try_from(&ihdr_chunk(&data)), Err(Error::Ihdr(IhdrError::InvalidInterlaceMethod(2)))));
+    }
+}
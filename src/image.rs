@@ -0,0 +1,577 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::compression::{self, CompressionError, CompressionOptions};
+use crate::filter::{self, FilterType};
+use crate::ihdr::{ColorType, Ihdr, InterlaceMethod};
+use crate::ordering;
+use crate::png::Png;
+use crate::{Error, Result};
+
+// x offset, y offset, x step and y step of the seven Adam7 passes
+const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)
+];
+
+// IDAT chunk size libpng uses by default
+const DEFAULT_IDAT_SIZE: usize = 8192;
+const MAX_CHUNK_LENGTH: usize = i32::MAX as usize;
+
+/// How `Image::encode_with` builds the png.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// zlib level, 0 to 9.
+    pub level: u32,
+    /// The compressed stream is split into IDAT chunks of at most this many bytes.
+    pub idat_size: usize
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions { level: compression::DEFAULT_LEVEL, idat_size: DEFAULT_IDAT_SIZE }
+    }
+}
+
+/// Decoded pixels of a png. Rows are stored top to bottom without filter
+/// bytes, in the png sample layout: 16 bit samples are big endian and depths
+/// below 8 pack several pixels into a byte, each row starting on a new byte.
+/// Indexed images keep their palette indices, the palette is kept alongside.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+    /// RGB entries from PLTE, empty when the png has none.
+    pub palette: Vec<[u8; 3]>,
+    pub pixels: Vec<u8>
+}
+
+impl TryFrom<&Png> for Image {
+    type Error = Error;
+
+    fn try_from(png: &Png) -> Result<Self> {
+        Image::decode_with(png, &CompressionOptions::default())
+    }
+}
+
+impl Image {
+    /// Concatenates and inflates the IDAT chunks, then undoes the scanline
+    /// filters and interlacing. Inflating stops at whichever is smaller of the
+    /// size the IHDR calls for and `options.max_decompressed_size`.
+    pub fn decode_with(png: &Png, options: &CompressionOptions) -> Result<Self> {
+        let ihdr = png.ihdr()?;
+        let palette = read_palette(png)?;
+        if ihdr.color_type == ColorType::Indexed && palette.is_empty() {
+            return Err(ImageError::MissingPalette.into());
+        }
+
+        let compressed: Vec<u8> = png.chunks()
+            .iter()
+            .filter(|chunk| ordering::is(chunk.chunk_type(), b"IDAT"))
+            .flat_map(|chunk| chunk.data().iter().copied())
+            .collect();
+        if compressed.is_empty() {
+            return Err(ImageError::MissingImageData.into());
+        }
+
+        let expected = filtered_length(&ihdr).ok_or(ImageError::TooLarge)?;
+        if expected > options.max_decompressed_size {
+            return Err(CompressionError::TooLarge(options.max_decompressed_size).into());
+        }
+        let mut filtered = compression::inflate(&compressed, expected)?;
+        if filtered.len() != expected {
+            return Err(ImageError::InvalidDataLength { expected, found: filtered.len() }.into());
+        }
+        trace!("image {}x{} inflated {} bytes of IDAT into {}", ihdr.width, ihdr.height, compressed.len(), expected);
+
+        let mut image = Image {
+            width: ihdr.width,
+            height: ihdr.height,
+            color_type: ihdr.color_type,
+            bit_depth: ihdr.bit_depth,
+            palette,
+            pixels: Vec::new()
+        };
+        image.pixels = match ihdr.interlace_method {
+            InterlaceMethod::None => {
+                unfilter_pass(&mut filtered, image.row_length(ihdr.width), image.bytes_per_pixel())?;
+                strip_filter_bytes(&filtered, image.row_length(ihdr.width))
+            }
+            InterlaceMethod::Adam7 => image.deinterlace(&mut filtered)?
+        };
+        Ok(image)
+    }
+
+    /// Checks that `pixels` holds exactly `height` rows of `width` pixels in the
+    /// layout described on `Image`. Indexed images need a palette set before
+    /// they can be encoded.
+    pub fn new(width: u32, height: u32, color_type: ColorType, bit_depth: u8, pixels: Vec<u8>) -> Result<Self> {
+        let image = Image { width, height, color_type, bit_depth, palette: Vec::new(), pixels };
+        image.ihdr().validate()?;
+        let expected = image.row_length(width) * height as usize;
+        if image.pixels.len() != expected {
+            return Err(ImageError::InvalidDataLength { expected, found: image.pixels.len() }.into());
+        }
+        Ok(image)
+    }
+
+    /// The non interlaced IHDR describing this image.
+    pub fn ihdr(&self) -> Ihdr {
+        Ihdr {
+            width: self.width,
+            height: self.height,
+            bit_depth: self.bit_depth,
+            color_type: self.color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: InterlaceMethod::None
+        }
+    }
+
+    /// Encodes with the default level and IDAT size, see `encode_with`.
+    pub fn encode(&self) -> Result<Png> {
+        self.encode_with(&EncodeOptions::default())
+    }
+
+    /// Builds a png with IHDR, PLTE for indexed images, the IDAT chunks and IEND.
+    /// Each row gets whichever filter compresses best, except for indexed and
+    /// sub byte images where filtering rarely helps and the spec suggests None.
+    pub fn encode_with(&self, options: &EncodeOptions) -> Result<Png> {
+        let ihdr = self.ihdr();
+        ihdr.validate()?;
+        if options.idat_size == 0 || options.idat_size > MAX_CHUNK_LENGTH {
+            return Err(ImageError::InvalidIdatSize(options.idat_size).into());
+        }
+        let row_length = self.row_length(self.width);
+        let expected = row_length * self.height as usize;
+        if self.pixels.len() != expected {
+            return Err(ImageError::InvalidDataLength { expected, found: self.pixels.len() }.into());
+        }
+
+        let mut chunks = vec![ihdr.to_chunk()];
+        if self.color_type == ColorType::Indexed {
+            if self.palette.is_empty() {
+                return Err(ImageError::MissingPalette.into());
+            }
+            if self.palette.len() > 256 {
+                return Err(ImageError::PaletteTooLong(self.palette.len()).into());
+            }
+            let palette = self.palette.iter().flatten().copied().collect();
+            chunks.push(Chunk::new(ChunkType::from_str("PLTE").unwrap(), palette));
+        }
+
+        let adaptive = self.color_type != ColorType::Indexed && self.bit_depth >= 8;
+        let mut filtered = Vec::with_capacity(expected + self.height as usize);
+        let mut previous: &[u8] = &vec![0; row_length];
+        for row in self.pixels.chunks(row_length.max(1)) {
+            if adaptive {
+                filter::filter_adaptive(row, previous, self.bytes_per_pixel(), &mut filtered);
+            } else {
+                filter::filter(FilterType::None, row, previous, self.bytes_per_pixel(), &mut filtered);
+            }
+            previous = row;
+        }
+
+        let compressed = compression::deflate(&filtered, options.level)?;
+        trace!("image {}x{} deflated {} bytes into {}", self.width, self.height, filtered.len(), compressed.len());
+        for data in compressed.chunks(options.idat_size) {
+            chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), data.to_vec()));
+        }
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        Ok(Png::from_chunks(chunks))
+    }
+
+    /// Swaps the pixel data of `png` for this image: IHDR and the IDAT chunks
+    /// are re-encoded in place, every other chunk, PLTE included, is kept.
+    pub fn encode_into(&self, png: &Png, options: &EncodeOptions) -> Result<Png> {
+        let encoded = self.encode_with(options)?;
+        let mut idats = encoded.chunks()
+            .iter()
+            .filter(|chunk| ordering::is(chunk.chunk_type(), b"IDAT"))
+            .cloned();
+
+        let mut chunks = Vec::with_capacity(png.chunks().len());
+        for chunk in png.chunks() {
+            if ordering::is(chunk.chunk_type(), b"IHDR") {
+                chunks.push(self.ihdr().to_chunk());
+            } else if ordering::is(chunk.chunk_type(), b"IDAT") {
+                // the whole new stream goes where the first old IDAT was
+                chunks.extend(idats.by_ref());
+            } else {
+                chunks.push(chunk.clone());
+            }
+        }
+        Ok(Png::from_chunks(chunks))
+    }
+
+    /// Bits used by a single pixel, all channels together.
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    /// Bytes in one unfiltered row of `width` pixels.
+    pub fn row_length(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// The raw bytes of the pixel at `x`, `y`; for depths below 8 this is the
+    /// byte the pixel is packed into.
+    pub fn pixel(&self, x: u32, y: u32) -> &[u8] {
+        let start = y as usize * self.row_length(self.width) + x as usize * self.bits_per_pixel() / 8;
+        &self.pixels[start..start + self.bytes_per_pixel()]
+    }
+
+    // the distance filters look back, whole bytes and at least one
+    fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    // unfilters each pass and scatters its pixels into a full size buffer
+    fn deinterlace(&self, filtered: &mut [u8]) -> Result<Vec<u8>> {
+        let row_length = self.row_length(self.width);
+        let bits = self.bits_per_pixel();
+        let mut pixels = vec![0; row_length * self.height as usize];
+
+        let mut start = 0;
+        for pass in passes(self.width, self.height) {
+            let pass_row = self.row_length(pass.width);
+            let end = start + pass.height as usize * (pass_row + 1);
+            let rows = &mut filtered[start..end];
+            unfilter_pass(rows, pass_row, self.bytes_per_pixel())?;
+
+            for row in 0..pass.height {
+                let source = &rows[row as usize * (pass_row + 1) + 1..][..pass_row];
+                let y = pass.y + row * pass.y_step;
+                for column in 0..pass.width {
+                    let x = pass.x + column * pass.x_step;
+                    copy_bits(source, column as usize * bits,
+                        &mut pixels[y as usize * row_length..], x as usize * bits, bits);
+                }
+            }
+            start = end;
+        }
+        Ok(pixels)
+    }
+}
+
+// one Adam7 pass: its size in pixels and where its pixels land in the image
+struct Pass {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    x_step: u32,
+    y_step: u32
+}
+
+// the non empty Adam7 passes of a `width` x `height` image, in stream order
+fn passes(width: u32, height: u32) -> Vec<Pass> {
+    ADAM7_PASSES
+        .iter()
+        .map(|&(x, y, x_step, y_step)| Pass {
+            width: (width + x_step - 1 - x) / x_step,
+            height: (height + y_step - 1 - y) / y_step,
+            x,
+            y,
+            x_step,
+            y_step
+        })
+        .filter(|pass| pass.width > 0 && pass.height > 0)
+        .collect()
+}
+
+// how many bytes the inflated IDAT stream must hold, filter bytes included
+fn filtered_length(ihdr: &Ihdr) -> Option<usize> {
+    let bits = ihdr.color_type.channels() * ihdr.bit_depth as usize;
+    let pass_length = |width: u32, height: u32| {
+        (width as usize).checked_mul(bits)?.div_ceil(8).checked_add(1)?.checked_mul(height as usize)
+    };
+    match ihdr.interlace_method {
+        InterlaceMethod::None => pass_length(ihdr.width, ihdr.height),
+        InterlaceMethod::Adam7 => passes(ihdr.width, ihdr.height)
+            .into_iter()
+            .try_fold(0usize, |total, pass| total.checked_add(pass_length(pass.width, pass.height)?))
+    }
+}
+
+// `rows` is a sequence of filter byte + `row_length` bytes, unfiltered in place
+fn unfilter_pass(rows: &mut [u8], row_length: usize, bytes_per_pixel: usize) -> Result<()> {
+    let mut previous = vec![0; row_length];
+    for row in rows.chunks_mut(row_length + 1) {
+        let (filter_byte, row) = row.split_first_mut().expect("rows are never empty");
+        filter::unfilter(FilterType::from_byte(*filter_byte)?, row, &previous, bytes_per_pixel);
+        previous.copy_from_slice(row);
+    }
+    Ok(())
+}
+
+fn strip_filter_bytes(rows: &[u8], row_length: usize) -> Vec<u8> {
+    rows.chunks(row_length + 1).flat_map(|row| row[1..].iter().copied()).collect()
+}
+
+// copies `count` bits, most significant first as png packs them
+fn copy_bits(source: &[u8], source_bit: usize, target: &mut [u8], target_bit: usize, count: usize) {
+    if source_bit.is_multiple_of(8) && target_bit.is_multiple_of(8) && count.is_multiple_of(8) {
+        let (from, to, length) = (source_bit / 8, target_bit / 8, count / 8);
+        target[to..to + length].copy_from_slice(&source[from..from + length]);
+        return;
+    }
+    for i in 0..count {
+        let (from, to) = (source_bit + i, target_bit + i);
+        let bit = (source[from / 8] >> (7 - from % 8)) & 1;
+        target[to / 8] = (target[to / 8] & !(1 << (7 - to % 8))) | (bit << (7 - to % 8));
+    }
+}
+
+fn read_palette(png: &Png) -> Result<Vec<[u8; 3]>> {
+    let plte = match png.chunk_by_type("PLTE") {
+        Some(plte) => plte,
+        None => return Ok(Vec::new())
+    };
+    if plte.data().len() % 3 != 0 {
+        return Err(ImageError::InvalidPalette(plte.data().len()).into());
+    }
+    Ok(plte.data().chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect())
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    MissingImageData,
+    MissingPalette,
+    InvalidPalette(usize),
+    PaletteTooLong(usize),
+    InvalidFilterType(u8),
+    InvalidDataLength { expected: usize, found: usize },
+    InvalidIdatSize(usize),
+    TooLarge
+}
+
+impl std::error::Error for ImageError {}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ImageError::MissingImageData => write!(f, "Png has no IDAT chunks"),
+            ImageError::MissingPalette => write!(f, "Indexed image has no PLTE chunk"),
+            ImageError::InvalidPalette(length) => write!(f, "PLTE length {} is not a multiple of 3", length),
+            ImageError::PaletteTooLong(entries) => write!(f, "Palette has {} entries, at most 256 allowed", entries),
+            ImageError::InvalidFilterType(byte) => write!(f, "Unknown scanline filter type {}", byte),
+            ImageError::InvalidDataLength { expected, found } =>
+                write!(f, "Image data should inflate to {} bytes, found {}", expected, found),
+            ImageError::InvalidIdatSize(size) => write!(f, "IDAT size must be 1 to {}, found {}", MAX_CHUNK_LENGTH, size),
+            ImageError::TooLarge => write!(f, "Image dimensions are too large to decode")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::Severity;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn ihdr(width: u32, height: u32, color_type: ColorType, bit_depth: u8, interlace_method: InterlaceMethod) -> Chunk {
+        Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method
+        }.to_chunk()
+    }
+
+    // png made of IHDR, an optional PLTE and the filtered rows split over two IDATs
+    fn testing_png(ihdr_chunk: Chunk, palette: Option<Vec<u8>>, filtered: &[u8]) -> Png {
+        let compressed = compression::deflate(filtered, 9).unwrap();
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        let mut chunks = vec![ihdr_chunk];
+        chunks.extend(palette.map(|palette| chunk("PLTE", palette)));
+        chunks.push(chunk("IDAT", first.to_vec()));
+        chunks.push(chunk("IDAT", second.to_vec()));
+        chunks.push(chunk("IEND", Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_decode_rgb_with_filters() {
+        // 2x3 rgb, rows use the None, Sub and Up filters
+        #[rustfmt::skip]
+        let filtered = [
+            0, 10, 20, 30, 40, 50, 60,
+            1, 1, 2, 3, 1, 1, 1,
+            2, 5, 5, 5, 5, 5, 5
+        ];
+        let png = testing_png(ihdr(2, 3, ColorType::Rgb, 8, InterlaceMethod::None), None, &filtered);
+        let image = Image::try_from(&png).unwrap();
+
+        assert_eq!((image.width, image.height), (2, 3));
+        assert_eq!(image.pixels, vec![10, 20, 30, 40, 50, 60, 1, 2, 3, 2, 3, 4, 6, 7, 8, 7, 8, 9]);
+        assert_eq!(image.pixel(1, 2), &[7, 8, 9]);
+    }
+
+    #[test]
+    fn test_decode_average_and_paeth() {
+        #[rustfmt::skip]
+        let filtered = [
+            0, 100, 200,
+            3, 0, 10,
+            4, 1, 1
+        ];
+        let png = testing_png(ihdr(2, 3, ColorType::Grayscale, 8, InterlaceMethod::None), None, &filtered);
+        let image = Image::try_from(&png).unwrap();
+
+        // average: 0 + 100 / 2, 10 + (50 + 200) / 2; paeth picks above for both
+        assert_eq!(image.pixels, vec![100, 200, 50, 135, 51, 136]);
+    }
+
+    #[test]
+    fn test_decode_indexed_low_bit_depth() {
+        // 5 pixels at 2 bits fit in 2 bytes per row
+        let filtered = [0, 0b00_01_10_11, 0b01_000000, 0, 0b11_10_01_00, 0b00_000000];
+        let palette = vec![0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let png = testing_png(ihdr(5, 2, ColorType::Indexed, 2, InterlaceMethod::None), Some(palette), &filtered);
+        let image = Image::try_from(&png).unwrap();
+
+        assert_eq!(image.palette.len(), 4);
+        assert_eq!(image.palette[1], [255, 0, 0]);
+        assert_eq!(image.pixels, vec![0b00_01_10_11, 0b01_000000, 0b11_10_01_00, 0]);
+    }
+
+    #[test]
+    fn test_decode_adam7() {
+        // 3x3 gray, pass 1 holds (0,0), pass 4 holds (2,0), pass 5 holds (0,2) and (2,2),
+        // pass 6 holds (1,0) and (1,2), pass 7 holds row 1
+        #[rustfmt::skip]
+        let filtered = [
+            0, 1,
+            0, 3,
+            0, 7, 9,
+            0, 2, 0, 8,
+            0, 4, 5, 6
+        ];
+        let png = testing_png(ihdr(3, 3, ColorType::Grayscale, 8, InterlaceMethod::Adam7), None, &filtered);
+        let image = Image::try_from(&png).unwrap();
+
+        assert_eq!(image.pixels, (1..=9).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_decode_adam7_one_bit() {
+        // 2x2 at 1 bit: pass 1 (0,0), pass 6 (1,0), pass 7 row 1
+        let filtered = [0, 0b1000_0000, 0, 0b0000_0000, 0, 0b1100_0000];
+        let png = testing_png(ihdr(2, 2, ColorType::Grayscale, 1, InterlaceMethod::Adam7), None, &filtered);
+        let image = Image::try_from(&png).unwrap();
+
+        assert_eq!(image.pixels, vec![0b1000_0000, 0b1100_0000]);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let png = testing_png(ihdr(2, 2, ColorType::Grayscale, 8, InterlaceMethod::None), None, &[0, 1, 2, 5, 3, 4]);
+        assert!(matches!(Image::try_from(&png), Err(Error::Image(ImageError::InvalidFilterType(5)))));
+
+        let png = testing_png(ihdr(2, 2, ColorType::Grayscale, 8, InterlaceMethod::None), None, &[0, 1, 2]);
+        assert!(matches!(Image::try_from(&png), Err(Error::Image(ImageError::InvalidDataLength { expected: 6, found: 3 }))));
+
+        let png = testing_png(ihdr(1, 1, ColorType::Indexed, 8, InterlaceMethod::None), None, &[0, 0]);
+        assert!(matches!(Image::try_from(&png), Err(Error::Image(ImageError::MissingPalette))));
+
+        let png = Png::from_chunks(vec![ihdr(1, 1, ColorType::Grayscale, 8, InterlaceMethod::None)]);
+        assert!(matches!(Image::try_from(&png), Err(Error::Image(ImageError::MissingImageData))));
+
+        let png = testing_png(ihdr(100, 100, ColorType::Rgba, 16, InterlaceMethod::None), None, &[0]);
+        let options = CompressionOptions { max_decompressed_size: 1000 };
+        assert!(matches!(Image::decode_with(&png, &options), Err(Error::Compression(CompressionError::TooLarge(1000)))));
+
+        // larger than the default cap, decoding it needs a higher one
+        let png = testing_png(ihdr(4096, 4096, ColorType::Rgba, 8, InterlaceMethod::None), None, &[0; 16]);
+        assert!(matches!(Image::try_from(&png), Err(Error::Compression(CompressionError::TooLarge(_)))));
+        let options = CompressionOptions { max_decompressed_size: 128 * 1024 * 1024 };
+        assert!(matches!(Image::decode_with(&png, &options),
+            Err(Error::Image(ImageError::InvalidDataLength { expected: 67_112_960, found: 16 }))));
+    }
+
+
+    #[test]
+    fn test_encode_round_trip() {
+        for (color_type, bit_depth) in [
+            (ColorType::Grayscale, 1),
+            (ColorType::Grayscale, 4),
+            (ColorType::Grayscale, 16),
+            (ColorType::Rgb, 8),
+            (ColorType::GrayscaleAlpha, 8),
+            (ColorType::Rgba, 16)
+        ] {
+            let row_length = (7 * color_type.channels() * bit_depth as usize).div_ceil(8);
+            let pixels: Vec<u8> = (0..row_length * 5).map(|i| (i * 37 % 251) as u8).collect();
+            let image = Image::new(7, 5, color_type, bit_depth, pixels).unwrap();
+
+            let png = image.encode().unwrap();
+            let decoded = Image::try_from(&Png::try_from(png.as_bytes().as_slice()).unwrap()).unwrap();
+            assert_eq!(decoded, image, "{:?} {}", color_type, bit_depth);
+        }
+    }
+
+    #[test]
+    fn test_encode_indexed() {
+        let mut image = Image::new(4, 2, ColorType::Indexed, 8, vec![0, 1, 2, 1, 2, 1, 0, 0]).unwrap();
+        assert!(matches!(image.encode(), Err(Error::Image(ImageError::MissingPalette))));
+        image.palette = vec![[0; 3]; 257];
+        assert!(matches!(image.encode(), Err(Error::Image(ImageError::PaletteTooLong(257)))));
+
+        image.palette = vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        let png = image.encode().unwrap();
+        assert_eq!(png.chunks()[1].chunk_type().to_string(), "PLTE");
+        assert_eq!(Image::try_from(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn test_encode_splits_idat() {
+        let pixels: Vec<u8> = (0..64 * 64 * 3).map(|i| (i * 7919 % 256) as u8).collect();
+        let image = Image::new(64, 64, ColorType::Rgb, 8, pixels).unwrap();
+        let png = image.encode_with(&EncodeOptions { idat_size: 100, ..Default::default() }).unwrap();
+
+        let idats: Vec<&Chunk> = png.chunks().iter().filter(|chunk| ordering::is(chunk.chunk_type(), b"IDAT")).collect();
+        assert!(idats.len() > 1);
+        assert!(idats.iter().all(|chunk| chunk.length() <= 100));
+        assert!(png.validate().iter().all(|finding| finding.severity != Severity::Error));
+        assert_eq!(Image::try_from(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        assert!(matches!(Image::new(2, 2, ColorType::Rgb, 8, vec![0; 11]),
+            Err(Error::Image(ImageError::InvalidDataLength { expected: 12, found: 11 }))));
+        assert!(matches!(Image::new(2, 2, ColorType::Rgb, 4, vec![0; 6]), Err(Error::Ihdr(_))));
+
+        let image = Image::new(1, 1, ColorType::Grayscale, 8, vec![0]).unwrap();
+        assert!(matches!(image.encode_with(&EncodeOptions { idat_size: 0, ..Default::default() }),
+            Err(Error::Image(ImageError::InvalidIdatSize(0)))));
+        assert!(image.encode_with(&EncodeOptions { level: 10, ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_encode_into_keeps_other_chunks() {
+        let image = Image::new(2, 2, ColorType::Grayscale, 8, vec![1, 2, 3, 4]).unwrap();
+        let mut png = image.encode_with(&EncodeOptions { level: 0, idat_size: 4 }).unwrap();
+        png.insert_chunk_before("IDAT", Chunk::new(ChunkType::from_str("gAMA").unwrap(), vec![0, 0, 177, 143])).unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"kept".to_vec()));
+
+        let changed = Image { pixels: vec![4, 3, 2, 1], ..image };
+        let rebuilt = changed.encode_into(&png, &EncodeOptions::default()).unwrap();
+        let types: Vec<String> = rebuilt.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+
+        assert_eq!(types, vec!["IHDR", "gAMA", "IDAT", "ruSt", "IEND"]);
+        assert_eq!(Image::try_from(&rebuilt).unwrap(), changed);
+    }
+}
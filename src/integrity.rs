@@ -0,0 +1,98 @@
+use std::fmt::Display;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::chunk_type::ChunkType;
+use crate::Result;
+
+/// Length of the HMAC-SHA256 tag appended to a payload.
+pub const TAG_LENGTH: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Appends an HMAC-SHA256 tag over `chunk_type` and `data`, so the bytes
+/// can't be edited or moved to another chunk type unnoticed.
+pub fn append_tag(chunk_type: &ChunkType, data: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut tagged = Vec::with_capacity(data.len() + TAG_LENGTH);
+    tagged.extend_from_slice(data);
+    tagged.extend_from_slice(&mac(chunk_type, data, key).finalize().into_bytes());
+    tagged
+}
+
+/// Checks the tag written by `append_tag` and returns the data in front of it.
+pub fn verify_tag<'a>(chunk_type: &ChunkType, tagged: &'a [u8], key: &[u8]) -> Result<&'a [u8]> {
+    if tagged.len() < TAG_LENGTH {
+        return Err(HmacError::Truncated.into());
+    }
+    let (data, tag) = tagged.split_at(tagged.len() - TAG_LENGTH);
+    // verify_slice compares in constant time
+    mac(chunk_type, data, key)
+        .verify_slice(tag)
+        .map_err(|_| HmacError::Mismatch)?;
+    Ok(data)
+}
+
+fn mac(chunk_type: &ChunkType, data: &[u8], key: &[u8]) -> HmacSha256 {
+    // hmac takes keys of any length
+    let mut mac = HmacSha256::new_from_slice(key).unwrap();
+    mac.update(&chunk_type.bytes());
+    mac.update(data);
+    mac
+}
+
+#[derive(Debug)]
+pub enum HmacError {
+    Truncated,
+    Untagged,
+    MissingKey,
+    Mismatch
+}
+
+impl std::error::Error for HmacError {}
+
+impl Display for HmacError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HmacError::Truncated => write!(f, "Hidden data is too short to carry an hmac tag"),
+            HmacError::Untagged => write!(f, "Hidden data has no hmac tag to check"),
+            HmacError::MissingKey => write!(f, "Hidden data carries an hmac tag, use --hmac-key to check it"),
+            HmacError::Mismatch => write!(f, "Hmac tag doesn't match, wrong key or the message was changed")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use std::str::FromStr;
+
+    fn chunk_type(name: &str) -> ChunkType {
+        ChunkType::from_str(name).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let tagged = append_tag(&chunk_type("ruSt"), b"hidden message", b"key");
+        assert_eq!(tagged.len(), 14 + TAG_LENGTH);
+        assert_eq!(verify_tag(&chunk_type("ruSt"), &tagged, b"key").unwrap(), b"hidden message");
+    }
+
+    #[test]
+    fn test_edits_are_detected() {
+        let mut tagged = append_tag(&chunk_type("ruSt"), b"hidden message", b"key");
+        assert!(matches!(verify_tag(&chunk_type("ruSt"), &tagged, b"other key"), Err(Error::Hmac(HmacError::Mismatch))));
+        assert!(matches!(verify_tag(&chunk_type("moVe"), &tagged, b"key"), Err(Error::Hmac(HmacError::Mismatch))));
+
+        tagged[0] ^= 1;
+        assert!(matches!(verify_tag(&chunk_type("ruSt"), &tagged, b"key"), Err(Error::Hmac(HmacError::Mismatch))));
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(matches!(verify_tag(&chunk_type("ruSt"), &[0; TAG_LENGTH - 1], b"key"), Err(Error::Hmac(HmacError::Truncated))));
+        let empty = append_tag(&chunk_type("ruSt"), b"", b"key");
+        assert!(verify_tag(&chunk_type("ruSt"), &empty, b"key").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt::Display;
+
+use crate::ihdr::ColorType;
+use crate::image::Image;
+use crate::Result;
+
+/// Bytes in front of the payload: its length and crc32, both big endian.
+pub const HEADER_LENGTH: usize = 8;
+
+/// Which samples carry the message and how many of their low bits are used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LsbOptions {
+    /// 1 to 8, more bits hold more data but change the image more.
+    pub bits_per_channel: u8,
+    /// Bit n selects the n-th sample of each pixel, so 0b0111 is RGB without alpha.
+    pub channel_mask: u8
+}
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        LsbOptions { bits_per_channel: 1, channel_mask: 0b1111 }
+    }
+}
+
+impl LsbOptions {
+    fn validate(&self, image: &Image) -> Result<()> {
+        if !(1..=8).contains(&self.bits_per_channel) {
+            return Err(LsbError::InvalidBitsPerChannel(self.bits_per_channel).into());
+        }
+        if image.color_type == ColorType::Indexed || image.bit_depth < 8 {
+            return Err(LsbError::UnsupportedImage(image.color_type, image.bit_depth).into());
+        }
+        let channels = image.color_type.channels();
+        if self.channel_mask & ((1 << channels) - 1) == 0 {
+            return Err(LsbError::InvalidChannelMask(self.channel_mask).into());
+        }
+        Ok(())
+    }
+}
+
+/// How many payload bytes fit in `image` once the header is accounted for.
+pub fn capacity(image: &Image, options: &LsbOptions) -> Result<usize> {
+    options.validate(image)?;
+    let bits = carrier_samples(image, options).count() * options.bits_per_channel as usize;
+    Ok((bits / 8).saturating_sub(HEADER_LENGTH))
+}
+
+/// Writes a header and `payload` into the low bits of the selected samples.
+pub fn embed(image: &mut Image, payload: &[u8], options: &LsbOptions) -> Result<()> {
+    let available = capacity(image, options)?;
+    if payload.len() > available || payload.len() > u32::MAX as usize {
+        return Err(LsbError::PayloadTooLarge { needed: payload.len(), available }.into());
+    }
+
+    let mut message = Vec::with_capacity(HEADER_LENGTH + payload.len());
+    message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    message.extend_from_slice(&crc::crc32::checksum_ieee(payload).to_be_bytes());
+    message.extend_from_slice(payload);
+
+    let bits_per_channel = options.bits_per_channel as usize;
+    let mask = low_bits(options.bits_per_channel);
+    let needed = (message.len() * 8).div_ceil(bits_per_channel);
+    for (index, sample) in carrier_samples(image, options).take(needed).enumerate() {
+        let first_bit = index * bits_per_channel;
+        let value = (0..bits_per_channel).fold(0u8, |value, i| (value << 1) | bit_at(&message, first_bit + i));
+        image.pixels[sample] = (image.pixels[sample] & !mask) | value;
+    }
+    trace!("lsb embedded {} bytes, {} bits per channel", payload.len(), bits_per_channel);
+    Ok(())
+}
+
+/// Reads back a payload written by `embed` with the same options, checking
+/// its length and checksum.
+pub fn extract(image: &Image, options: &LsbOptions) -> Result<Vec<u8>> {
+    options.validate(image)?;
+    let mut bits = carrier_samples(image, options).flat_map(|sample| {
+        let value = image.pixels[sample];
+        (0..options.bits_per_channel).rev().map(move |i| (value >> i) & 1)
+    });
+    let mut read_bytes = |count: usize| -> Option<Vec<u8>> {
+        (0..count).map(|_| (0..8).try_fold(0u8, |byte, _| Some((byte << 1) | bits.next()?))).collect()
+    };
+
+    let header = read_bytes(HEADER_LENGTH).ok_or(LsbError::NoPayload)?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let checksum = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    if length > capacity(image, options)? {
+        return Err(LsbError::NoPayload.into());
+    }
+    let payload = read_bytes(length).ok_or(LsbError::NoPayload)?;
+    if crc::crc32::checksum_ieee(&payload) != checksum {
+        return Err(LsbError::ChecksumMismatch.into());
+    }
+    Ok(payload)
+}
+
+// index into `image.pixels` of the least significant byte of every selected
+// sample, in the order bits are written; it doesn't borrow the image, so
+// `embed` can write the pixels while walking it
+fn carrier_samples(image: &Image, options: &LsbOptions) -> impl Iterator<Item = usize> {
+    let bytes_per_sample = image.bit_depth as usize / 8;
+    let channels = image.color_type.channels();
+    let channel_mask = options.channel_mask;
+    (0..image.pixels.len() / bytes_per_sample)
+        .filter(move |sample| channel_mask & (1 << (sample % channels)) != 0)
+        .map(move |sample| sample * bytes_per_sample + bytes_per_sample - 1)
+}
+
+fn bit_at(bytes: &[u8], index: usize) -> u8 {
+    bytes.get(index / 8).map_or(0, |byte| (byte >> (7 - index % 8)) & 1)
+}
+
+fn low_bits(count: u8) -> u8 {
+    (0xffu16 >> (8 - count)) as u8
+}
+
+#[derive(Debug)]
+pub enum LsbError {
+    InvalidBitsPerChannel(u8),
+    InvalidChannelMask(u8),
+    UnsupportedImage(ColorType, u8),
+    PayloadTooLarge { needed: usize, available: usize },
+    NoPayload,
+    ChecksumMismatch
+}
+
+impl std::error::Error for LsbError {}
+
+impl Display for LsbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LsbError::InvalidBitsPerChannel(bits) => write!(f, "Bits per channel must be 1 to 8, found {}", bits),
+            LsbError::InvalidChannelMask(mask) => write!(f, "Channel mask {:#06b} selects no channel of this image", mask),
+            LsbError::UnsupportedImage(color_type, bit_depth) =>
+                write!(f, "Can't hide data in {:?} images with bit depth {}", color_type, bit_depth),
+            LsbError::PayloadTooLarge { needed, available } =>
+                write!(f, "Message needs {} bytes but the image only holds {}", needed, available),
+            LsbError::NoPayload => write!(f, "No hidden message found in the pixel data"),
+            LsbError::ChecksumMismatch => write!(f, "Hidden message is damaged, checksum doesn't match")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn testing_image(color_type: ColorType, bit_depth: u8) -> Image {
+        let length = 16 * 16 * color_type.channels() * bit_depth as usize / 8;
+        let pixels = (0..length).map(|i| (i * 31 % 256) as u8).collect();
+        Image::new(16, 16, color_type, bit_depth, pixels).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for bits_per_channel in 1..=8 {
+            let options = LsbOptions { bits_per_channel, ..Default::default() };
+            let mut image = testing_image(ColorType::Rgba, 8);
+            embed(&mut image, b"hidden in plain sight", &options).unwrap();
+            assert_eq!(extract(&image, &options).unwrap(), b"hidden in plain sight");
+        }
+    }
+
+    #[test]
+    fn test_only_low_bits_change() {
+        let original = testing_image(ColorType::Rgb, 8);
+        let mut image = original.clone();
+        embed(&mut image, &[0xff; 20], &LsbOptions { bits_per_channel: 2, ..Default::default() }).unwrap();
+
+        assert!(image.pixels.iter().zip(&original.pixels).all(|(a, b)| a & !0b11 == b & !0b11));
+        assert_ne!(image.pixels, original.pixels);
+    }
+
+    #[test]
+    fn test_channel_mask() {
+        let original = testing_image(ColorType::Rgba, 8);
+        let mut image = original.clone();
+        let options = LsbOptions { bits_per_channel: 1, channel_mask: 0b1000 };
+        embed(&mut image, b"alpha only", &options).unwrap();
+
+        for (index, (a, b)) in image.pixels.iter().zip(&original.pixels).enumerate() {
+            if index % 4 != 3 {
+                assert_eq!(a, b);
+            }
+        }
+        assert_eq!(extract(&image, &options).unwrap(), b"alpha only");
+        assert!(extract(&image, &LsbOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_sixteen_bit_changes_low_byte() {
+        let original = testing_image(ColorType::Grayscale, 16);
+        let mut image = original.clone();
+        embed(&mut image, b"deep", &LsbOptions::default()).unwrap();
+
+        assert!(image.pixels.iter().step_by(2).eq(original.pixels.iter().step_by(2)));
+        assert_eq!(extract(&image, &LsbOptions::default()).unwrap(), b"deep");
+    }
+
+    #[test]
+    fn test_capacity() {
+        let image = testing_image(ColorType::Rgb, 8);
+        // 768 samples, one bit each
+        assert_eq!(capacity(&image, &LsbOptions::default()).unwrap(), 96 - HEADER_LENGTH);
+        let options = LsbOptions { bits_per_channel: 4, channel_mask: 0b001 };
+        assert_eq!(capacity(&image, &options).unwrap(), 128 - HEADER_LENGTH);
+
+        let mut image = image;
+        assert!(matches!(embed(&mut image, &[0; 89], &LsbOptions::default()),
+            Err(Error::Lsb(LsbError::PayloadTooLarge { needed: 89, available: 88 }))));
+    }
+
+    #[test]
+    fn test_invalid_options_and_images() {
+        let image = testing_image(ColorType::Rgb, 8);
+        assert!(capacity(&image, &LsbOptions { bits_per_channel: 0, ..Default::default() }).is_err());
+        assert!(capacity(&image, &LsbOptions { bits_per_channel: 1, channel_mask: 0b1000 }).is_err());
+
+        let indexed = Image::new(8, 8, ColorType::Indexed, 8, vec![0; 64]).unwrap();
+        assert!(matches!(capacity(&indexed, &LsbOptions::default()), Err(Error::Lsb(LsbError::UnsupportedImage(..)))));
+    }
+
+    #[test]
+    fn test_damaged_payload() {
+        let mut image = testing_image(ColorType::Rgb, 8);
+        embed(&mut image, b"fragile", &LsbOptions::default()).unwrap();
+        let sample = (HEADER_LENGTH + 2) * 8;
+        image.pixels[sample] ^= 1;
+        assert!(matches!(extract(&image, &LsbOptions::default()), Err(Error::Lsb(LsbError::ChecksumMismatch))));
+
+        let clean = testing_image(ColorType::Rgb, 8);
+        assert!(extract(&clean, &LsbOptions::default()).is_err());
+    }
+}
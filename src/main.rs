@@ -2,35 +2,84 @@
 
 use clap::{App, Arg};
 use commands::{SubCommandType, get_subcommand, encode_operation, 
-    decode_operation, remove_operation, print_operation};
+    decode_operation, remove_operation, print_operation, validate_operation, repair_crc_operation,
+    sign_operation, verify_operation};
+
+#[macro_use]
+mod trace;
 
 mod args;
 mod chunk;
+mod chunk_ref;
 mod chunk_type;
 mod commands;
+mod compression;
+mod crypto;
+mod envelope;
+mod error;
+mod filter;
+mod fragment;
+mod ihdr;
+mod image;
+mod integrity;
+mod lsb;
+mod mapped;
+mod ordering;
+mod payload;
 mod png;
+mod reader;
+mod recover;
+mod repair;
+mod signature;
+mod text;
+mod validate;
+mod writer;
 
-pub type Error = Box<dyn std::error::Error>;
+pub use error::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 
-fn main() -> Result<()> {
-    
-    let matches = App::new("pngme")
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("error: {}", error);
+        std::process::exit(error.exit_code());
+    }
+}
+
+fn run() -> Result<()> {
+    let app = App::new("pngme")
                     .about("An app to encode and decode message into png files")
+                    .after_help(error::EXIT_CODES_HELP)
                     .subcommand(get_subcommand(SubCommandType::Encode))
                     .subcommand(get_subcommand(SubCommandType::Decode))
                     .subcommand(get_subcommand(SubCommandType::Remove))
                     .subcommand(get_subcommand(SubCommandType::Print))
-                    .get_matches();
+                    .subcommand(get_subcommand(SubCommandType::Validate))
+                    .subcommand(get_subcommand(SubCommandType::RepairCrc))
+                    .subcommand(get_subcommand(SubCommandType::Sign))
+                    .subcommand(get_subcommand(SubCommandType::Verify));
+
+    #[cfg(feature = "trace")]
+    let app = app.arg(Arg::new("verbose")
+                    .long("verbose")
+                    .global(true)
+                    .help("print parser debug output to stderr"));
+
+    let matches = app.get_matches();
+
+    #[cfg(feature = "trace")]
+    trace::set_verbose(matches.is_present("verbose"));
     
     match matches.subcommand() {
         Some(("encode", sub_matches)) => encode_operation(sub_matches),
         Some(("decode", sub_matches)) => decode_operation(sub_matches),
         Some(("remove", sub_matches)) => remove_operation(sub_matches),
         Some(("print", sub_matches)) => print_operation(sub_matches),
-        _ => eprintln!("not the droid you're looking for, use --help")
-    } 
-    Ok(())
+        Some(("validate", sub_matches)) => validate_operation(sub_matches),
+        Some(("repair-crc", sub_matches)) => repair_crc_operation(sub_matches),
+        Some(("sign", sub_matches)) => sign_operation(sub_matches),
+        Some(("verify", sub_matches)) => verify_operation(sub_matches),
+        _ => Err(Error::Usage(String::from("not the droid you're looking for, use --help")))
+    }
 }
 
 // these two must be in args.rs but for some reason 
@@ -40,7 +89,23 @@ pub enum ArgumentType {
     FilePath,
     ChunkType,
     Message,
-    OutputFile
+    OutputFile,
+    Strict,
+    Lenient,
+    Method,
+    Bits,
+    ChannelMask,
+    Passphrase,
+    FragmentSize,
+    Fragments,
+    File,
+    Output,
+    Compress,
+    MaxSize,
+    Key,
+    PublicKey,
+    Include,
+    HmacKey
 }
 
 pub fn get_argument(argument_type: ArgumentType) -> Arg<'static> {
@@ -59,6 +124,72 @@ pub fn get_argument(argument_type: ArgumentType) -> Arg<'static> {
         .help("the message that you wanna encode"),
         ArgumentType::OutputFile => Arg::new("output_file")
         .takes_value(true)
-        .help("output file")
+        .help("output file, - for stdout"),
+        ArgumentType::Strict => Arg::new("strict")
+        .long("strict")
+        .help("fail on warnings too"),
+        ArgumentType::Lenient => Arg::new("lenient")
+        .long("lenient")
+        .help("skip damaged chunks instead of failing"),
+        ArgumentType::Method => Arg::new("method")
+        .long("method")
+        .takes_value(true)
+        .possible_values(["chunk", "lsb"])
+        .help("hide the message in a chunk or in the pixels, lsb takes no chunk type"),
+        ArgumentType::Bits => Arg::new("bits")
+        .long("bits")
+        .takes_value(true)
+        .default_value("1")
+        .help("lsb: low bits used in each sample, 1 to 8"),
+        ArgumentType::ChannelMask => Arg::new("channel_mask")
+        .long("channel-mask")
+        .takes_value(true)
+        .default_value("15")
+        .help("lsb: bit n selects the n-th sample of each pixel"),
+        ArgumentType::Passphrase => Arg::new("passphrase")
+        .long("passphrase")
+        .takes_value(true)
+        .help("encrypt the message, decode needs the same passphrase"),
+        ArgumentType::FragmentSize => Arg::new("fragment_size")
+        .long("fragment-size")
+        .takes_value(true)
+        .help("split the message over numbered chunks of at most this many bytes"),
+        ArgumentType::Fragments => Arg::new("fragments")
+        .long("fragments")
+        .help("put back together a message written with --fragment-size"),
+        ArgumentType::File => Arg::new("file")
+        .long("file")
+        .takes_value(true)
+        .help("hide this file instead of a message"),
+        ArgumentType::Output => Arg::new("output")
+        .long("output")
+        .takes_value(true)
+        .help("save a file hidden with --file here, a directory keeps its name"),
+        ArgumentType::Compress => Arg::new("compress")
+        .long("compress")
+        .help("deflate the message before hiding it, decode notices by itself"),
+        ArgumentType::MaxSize => Arg::new("max_size")
+        .long("max-size")
+        .takes_value(true)
+        .default_value("67108864")
+        .help("refuse messages, and images for lsb, that inflate past this many bytes"),
+        ArgumentType::Key => Arg::new("key")
+        .long("key")
+        .required(true)
+        .takes_value(true)
+        .help("Ed25519 secret key file, 32 raw bytes or 64 hex digits"),
+        ArgumentType::PublicKey => Arg::new("public_key")
+        .long("public-key")
+        .required(true)
+        .takes_value(true)
+        .help("Ed25519 public key file, 32 raw bytes or 64 hex digits"),
+        ArgumentType::Include => Arg::new("include")
+        .long("include")
+        .takes_value(true)
+        .help("ancillary chunk types to sign too, comma separated: tEXt,iTXt"),
+        ArgumentType::HmacKey => Arg::new("hmac_key")
+        .long("hmac-key")
+        .takes_value(true)
+        .help("tag the message with hmac-sha256, decode refuses it without the same key")
     }
 }
\ No newline at end of file
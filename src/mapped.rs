@@ -0,0 +1,280 @@
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use memmap2::Mmap;
+
+use crate::chunk::Chunk;
+use crate::chunk_ref::{ChunkRef, ChunkRefs};
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{Ihdr, IhdrError};
+use crate::ordering;
+use crate::png::PngError;
+use crate::Result;
+
+/// Where a chunk sits in the file, `length` includes length, type and crc.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkLocation {
+    pub offset: usize,
+    pub length: usize,
+    pub chunk_type: ChunkType
+}
+
+/// A png file edited in place through a memory map. Chunks are read straight
+/// from the mapping and edits only rewrite the file from the changed chunk
+/// onwards, so touching a small chunk near the end of a huge file is cheap.
+/// Edits are not atomic, a failed write can leave the file damaged.
+pub struct MappedPng {
+    file: File,
+    // None while an edit changes the file, and for good if it failed
+    map: Option<Mmap>,
+    locations: Vec<ChunkLocation>
+}
+
+impl MappedPng {
+    /// Opens `path` for reading and writing, maps it and indexes its chunks.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let (map, locations) = Self::index(&file)?;
+        Ok(MappedPng { file, map: Some(map), locations })
+    }
+
+    fn map(&self) -> Result<&Mmap> {
+        self.map
+            .as_ref()
+            .ok_or_else(|| io::Error::other("png is no longer mapped after a failed edit").into())
+    }
+
+    fn index(file: &File) -> Result<(Mmap, Vec<ChunkLocation>)> {
+        // SAFETY: the mapping is only read while we hold the file, and every
+        // write we do goes through `splice`, which maps the file again afterwards.
+        // Other processes changing the file underneath us is not supported.
+        let map = unsafe { Mmap::map(file)? };
+
+        let mut locations = Vec::new();
+        let mut chunks = ChunkRefs::new(&map)?;
+        loop {
+            let offset = chunks.offset();
+            let chunk = match chunks.next() {
+                Some(chunk) => chunk?,
+                None => break
+            };
+            locations.push(ChunkLocation {
+                offset,
+                length: chunk.length() as usize + Chunk::METADATA_LENGTH,
+                chunk_type: *chunk.chunk_type()
+            });
+        }
+        Ok((map, locations))
+    }
+
+    /// Parses the IHDR, which has to be the first chunk.
+    pub fn ihdr(&self) -> Result<Ihdr> {
+        let first = self.locations.first().ok_or(IhdrError::MissingIhdr)?;
+        let chunk = self.chunk_at(first)?;
+        if !ordering::is(chunk.chunk_type(), &Ihdr::CHUNK_TYPE) {
+            return Err(IhdrError::NotIhdr(chunk.chunk_type().to_string()).into());
+        }
+        Ihdr::from_data(chunk.data())
+    }
+
+    /// Offsets and sizes of every chunk, in file order.
+    pub fn locations(&self) -> &[ChunkLocation] {
+        &self.locations
+    }
+
+    /// Borrows the chunk at `location` from the mapping.
+    pub fn chunk_at(&self, location: &ChunkLocation) -> Result<ChunkRef<'_>> {
+        ChunkRef::try_from(&self.map()?[location.offset..location.offset + location.length])
+    }
+
+    /// Lists the chunks without copying their data.
+    pub fn chunks(&self) -> Result<Vec<ChunkRef<'_>>> {
+        self.locations.iter().map(|location| self.chunk_at(location)).collect()
+    }
+
+    /// Finds the first chunk with the given type.
+    pub fn locate(&self, chunk_type: &str) -> Result<Option<ChunkLocation>> {
+        let wanted_chunk_type = ChunkType::from_str(chunk_type)?;
+        Ok(self.locations
+            .iter()
+            .find(|location| location.chunk_type == wanted_chunk_type)
+            .copied())
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Result<Option<ChunkRef<'_>>> {
+        match self.locate(chunk_type)? {
+            Some(location) => Ok(Some(self.chunk_at(&location)?)),
+            None => Ok(None)
+        }
+    }
+
+    /// Removes the first chunk of `chunk_type` from the file and returns it.
+    pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
+        let location = self.locate(chunk_type)?.ok_or(PngError::NotFoundChunk)?;
+        let removed = self.chunk_at(&location)?.into_owned();
+        self.splice(location.offset, location.offset + location.length, &[])?;
+        Ok(removed)
+    }
+
+    /// Replaces the first chunk of `chunk_type` with `chunk`. A same sized
+    /// chunk is overwritten where it is, nothing else in the file moves.
+    pub fn replace_chunk(&mut self, chunk_type: &str, chunk: &Chunk) -> Result<()> {
+        let location = self.locate(chunk_type)?.ok_or(PngError::NotFoundChunk)?;
+        self.splice(location.offset, location.offset + location.length, &chunk.as_bytes())
+    }
+
+    /// Adds `chunk` just before IEND, or at the end of the file when there is
+    /// no IEND. Only IEND itself has to be rewritten.
+    pub fn append_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        let offset = match self.locations.iter().rev().find(|location| ordering::is(&location.chunk_type, b"IEND")) {
+            Some(location) => location.offset,
+            None => self.map()?.len()
+        };
+        self.splice(offset, offset, &chunk.as_bytes())
+    }
+
+    // replaces bytes start..end of the file with `replacement`, only writing
+    // from `start` onwards, then maps and indexes the file again
+    fn splice(&mut self, start: usize, end: usize, replacement: &[u8]) -> Result<()> {
+        let map = self.map()?;
+        let tail = if replacement.len() == end - start {
+            Vec::new()
+        } else {
+            map[end..].to_vec()
+        };
+        let new_length = map.len() - (end - start) + replacement.len();
+
+        // the old mapping must be gone before the file shrinks under it, and
+        // stays gone if anything below fails
+        self.map = None;
+        self.locations.clear();
+        self.file.seek(SeekFrom::Start(start as u64))?;
+        self.file.write_all(replacement)?;
+        self.file.write_all(&tail)?;
+        self.file.set_len(new_length as u64)?;
+        self.file.flush()?;
+
+        let (map, locations) = Self::index(&self.file)?;
+        self.map = Some(map);
+        self.locations = locations;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::Png;
+    use crate::reader::PngReader;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.as_bytes().to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk_from_strings("FrSt", "I am the first chunk"),
+            chunk_from_strings("miDl", "I am another chunk"),
+            chunk_from_strings("LASt", "I am the last chunk"),
+        ])
+    }
+
+    fn testing_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("pngme-mapped-{}-{}.png", std::process::id(), name));
+        fs::write(&path, testing_png().as_bytes()).unwrap();
+        path
+    }
+
+    // these files have no IHDR, so read them chunk by chunk rather than as a `Png`
+    fn read_back(path: &Path) -> Vec<Chunk> {
+        let bytes = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        PngReader::new(bytes.as_slice()).unwrap().collect::<Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn test_locations() {
+        let path = testing_file("locations");
+        let mapped = MappedPng::open(&path).unwrap();
+        let offsets: Vec<usize> = mapped.locations().iter().map(|location| location.offset).collect();
+        let chunk = mapped.chunk_by_type("miDl").unwrap().unwrap();
+
+        assert_eq!(offsets, vec![8, 8 + 32, 8 + 32 + 30]);
+        assert_eq!(chunk.data_as_string().unwrap(), "I am another chunk");
+        drop(mapped);
+        read_back(&path);
+    }
+
+    #[test]
+    fn test_ihdr() {
+        let path = testing_file("ihdr");
+        let mapped = MappedPng::open(&path).unwrap();
+        assert!(matches!(mapped.ihdr(), Err(crate::Error::Ihdr(IhdrError::NotIhdr(_)))));
+        drop(mapped);
+        read_back(&path);
+
+        let path = std::env::temp_dir().join(format!("pngme-mapped-{}-real.png", std::process::id()));
+        let ihdr = Ihdr::from_data(&[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]).unwrap();
+        fs::write(&path, Png::from_chunks(vec![ihdr.to_chunk()]).as_bytes()).unwrap();
+        assert_eq!(MappedPng::open(&path).unwrap().ihdr().unwrap(), ihdr);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_chunk() {
+        let path = testing_file("remove");
+        let mut mapped = MappedPng::open(&path).unwrap();
+        let removed = mapped.remove_chunk("miDl").unwrap();
+
+        assert_eq!(removed.data_as_string().unwrap(), "I am another chunk");
+        assert_eq!(mapped.locations().len(), 2);
+        assert!(mapped.remove_chunk("miDl").is_err());
+        drop(mapped);
+
+        let chunks = read_back(&path);
+        let types: Vec<String> = chunks.iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "LASt"]);
+    }
+
+    #[test]
+    fn test_replace_chunk() {
+        let path = testing_file("replace");
+        let mut mapped = MappedPng::open(&path).unwrap();
+        mapped.replace_chunk("FrSt", &chunk_from_strings("FrSt", "same length chunk!!!")).unwrap();
+        mapped.replace_chunk("miDl", &chunk_from_strings("miDl", "longer than it was before")).unwrap();
+        drop(mapped);
+
+        let chunks = read_back(&path);
+        assert_eq!(chunks[0].data_as_string().unwrap(), "same length chunk!!!");
+        assert_eq!(chunks[1].data_as_string().unwrap(), "longer than it was before");
+        assert_eq!(chunks[2].data_as_string().unwrap(), "I am the last chunk");
+    }
+
+    #[test]
+    fn test_append_chunk() {
+        let path = testing_file("append");
+        let mut mapped = MappedPng::open(&path).unwrap();
+        mapped.append_chunk(&chunk_from_strings("TeSt", "Message")).unwrap();
+
+        assert_eq!(mapped.chunks().unwrap().len(), 4);
+        drop(mapped);
+        assert_eq!(read_back(&path)[3].data_as_string().unwrap(), "Message");
+    }
+
+    #[test]
+    fn test_append_chunk_before_iend() {
+        let path = testing_file("append-iend");
+        let mut mapped = MappedPng::open(&path).unwrap();
+        mapped.append_chunk(&chunk_from_strings("IEND", "")).unwrap();
+        mapped.append_chunk(&chunk_from_strings("TeSt", "Message")).unwrap();
+        drop(mapped);
+
+        let types: Vec<String> = read_back(&path).iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "miDl", "LASt", "TeSt", "IEND"]);
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt::Display;
+
+use crate::chunk_type::ChunkType;
+
+// chunks that must come before both PLTE and the first IDAT
+const BEFORE_PLTE: [&[u8; 4]; 6] = [b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP"];
+// chunks that must come after PLTE (when there is one) and before the first IDAT
+const AFTER_PLTE: [&[u8; 4]; 3] = [b"bKGD", b"hIST", b"tRNS"];
+// chunks that only need to come before the first IDAT
+const BEFORE_IDAT: [&[u8; 4]; 2] = [b"pHYs", b"sPLT"];
+
+/// A chunk sitting where the png spec doesn't allow it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderViolation {
+    /// Position of the offending chunk in the chunk list.
+    pub index: usize,
+    pub chunk_type: String,
+    pub reason: &'static str
+}
+
+impl Display for OrderViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at position {}: {}", self.chunk_type, self.index, self.reason)
+    }
+}
+
+/// Checks the order of `chunk_types` against the rules of the png spec and
+/// returns every violation found. Chunks the spec doesn't place are allowed
+/// anywhere between IHDR and IEND.
+pub fn order_violations(chunk_types: &[&ChunkType]) -> Vec<OrderViolation> {
+    let position = |name: &[u8; 4]| chunk_types.iter().position(|chunk_type| is(chunk_type, name));
+    let first_plte = position(b"PLTE");
+    let first_idat = position(b"IDAT");
+    let after_idat = |index: usize| first_idat.is_some_and(|idat| index > idat);
+    let after_plte = |index: usize| first_plte.is_some_and(|plte| index > plte);
+    let last = chunk_types.len().saturating_sub(1);
+
+    let mut violations = Vec::new();
+    for (index, chunk_type) in chunk_types.iter().enumerate() {
+        let reason = if is(chunk_type, b"IHDR") {
+            (index != 0).then_some("IHDR must be the first chunk")
+        } else if is(chunk_type, b"IEND") {
+            (index != last).then_some("IEND must be the last chunk")
+        } else if is(chunk_type, b"PLTE") {
+            after_idat(index).then_some("PLTE must come before IDAT")
+        } else if is(chunk_type, b"IDAT") {
+            (after_idat(index) && !is(chunk_types[index - 1], b"IDAT"))
+                .then_some("IDAT chunks must be consecutive")
+        } else if BEFORE_PLTE.iter().any(|name| is(chunk_type, name)) {
+            (after_plte(index) || after_idat(index)).then_some("must come before PLTE and IDAT")
+        } else if AFTER_PLTE.iter().any(|name| is(chunk_type, name)) {
+            if first_plte.is_some_and(|plte| index < plte) {
+                Some("must come after PLTE")
+            } else {
+                after_idat(index).then_some("must come before IDAT")
+            }
+        } else if BEFORE_IDAT.iter().any(|name| is(chunk_type, name)) {
+            after_idat(index).then_some("must come before IDAT")
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            violations.push(OrderViolation {
+                index,
+                chunk_type: chunk_type.to_string(),
+                reason
+            });
+        }
+    }
+    violations
+}
+
+pub fn is(chunk_type: &ChunkType, name: &[u8; 4]) -> bool {
+    chunk_type.bytes() == *name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn violations(names: &[&str]) -> Vec<OrderViolation> {
+        let chunk_types: Vec<ChunkType> = names.iter().map(|name| ChunkType::from_str(name).unwrap()).collect();
+        let chunk_types: Vec<&ChunkType> = chunk_types.iter().collect();
+        order_violations(&chunk_types)
+    }
+
+    #[test]
+    fn test_valid_order() {
+        assert!(violations(&["IHDR", "gAMA", "PLTE", "tRNS", "pHYs", "IDAT", "IDAT", "tEXt", "IEND"]).is_empty());
+        assert!(violations(&["IHDR", "ruSt", "IDAT", "ruSt", "IEND"]).is_empty());
+        assert!(violations(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_ihdr_and_iend_position() {
+        let found = violations(&["ruSt", "IHDR", "IDAT", "IEND", "ruSt"]);
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].index, found[0].chunk_type.as_str()), (1, "IHDR"));
+        assert_eq!((found[1].index, found[1].chunk_type.as_str()), (3, "IEND"));
+    }
+
+    #[test]
+    fn test_plte_before_idat() {
+        let found = violations(&["IHDR", "IDAT", "PLTE", "IEND"]);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].chunk_type, "PLTE");
+    }
+
+    #[test]
+    fn test_idat_consecutive() {
+        let found = violations(&["IHDR", "IDAT", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].index, 3);
+    }
+
+    #[test]
+    fn test_ancillary_placement() {
+        assert_eq!(violations(&["IHDR", "PLTE", "gAMA", "IDAT", "IEND"])[0].chunk_type, "gAMA");
+        assert_eq!(violations(&["IHDR", "IDAT", "iCCP", "IEND"])[0].chunk_type, "iCCP");
+        assert_eq!(violations(&["IHDR", "tRNS", "PLTE", "IDAT", "IEND"])[0].chunk_type, "tRNS");
+        assert_eq!(violations(&["IHDR", "IDAT", "bKGD", "IEND"])[0].chunk_type, "bKGD");
+        assert_eq!(violations(&["IHDR", "IDAT", "pHYs", "IEND"])[0].chunk_type, "pHYs");
+    }
+}
@@ -0,0 +1,283 @@
+use std::fmt::Display;
+
+use crate::chunk_type::ChunkType;
+use crate::compression;
+use crate::crypto::{self, CryptoError};
+use crate::integrity::{self, HmacError};
+use crate::Result;
+
+/// Starts every payload, followed by the flags byte that says what was done
+/// to the message, so nothing is ever guessed from the message itself.
+pub const MAGIC: [u8; 4] = *b"PMEp";
+/// The data after the header is a zlib stream.
+pub const FLAG_COMPRESSED: u8 = 0b0000_0001;
+/// The message is a `FileEnvelope`, not text.
+pub const FLAG_FILE: u8 = 0b0000_0010;
+/// The data after the header is sealed with `crypto::seal`.
+pub const FLAG_ENCRYPTED: u8 = 0b0000_0100;
+/// An hmac tag over the chunk type, header and data follows the data.
+pub const FLAG_TAGGED: u8 = 0b0000_1000;
+const KNOWN_FLAGS: u8 = FLAG_COMPRESSED | FLAG_FILE | FLAG_ENCRYPTED | FLAG_TAGGED;
+/// Magic, flags byte and the original length as a big endian u64.
+pub const HEADER_LENGTH: usize = MAGIC.len() + 1 + 8;
+
+/// What `pack` does to a message.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PackOptions<'a> {
+    /// The message is a `FileEnvelope`.
+    pub is_file: bool,
+    /// Deflate the message at this level.
+    pub compression_level: Option<u32>,
+    /// Encrypt the message, after compressing it since ciphertext doesn't compress.
+    pub passphrase: Option<&'a str>,
+    /// Tag the payload for the chunk type it goes into with this key.
+    pub hmac: Option<(&'a ChunkType, &'a [u8])>
+}
+
+/// What `unpack` accepts.
+#[derive(Debug, Clone, Copy)]
+pub struct UnpackOptions<'a> {
+    /// Messages that claim or turn out to be larger are refused.
+    pub max_size: usize,
+    /// Needed for encrypted payloads, refused for the rest.
+    pub passphrase: Option<&'a str>,
+    /// Chunk type and key to check the tag with, likewise needed for tagged
+    /// payloads only.
+    pub hmac: Option<(&'a ChunkType, &'a [u8])>
+}
+
+impl Default for UnpackOptions<'_> {
+    fn default() -> Self {
+        UnpackOptions { max_size: compression::DEFAULT_MAX_DECOMPRESSED_SIZE, passphrase: None, hmac: None }
+    }
+}
+
+/// A message taken out of a payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unpacked {
+    pub message: Vec<u8>,
+    /// The message is a `FileEnvelope`.
+    pub is_file: bool
+}
+
+/// Puts `message` behind a header recording the flags and its original length,
+/// compressed and encrypted as `options` ask.
+pub fn pack(message: &[u8], options: &PackOptions) -> Result<Vec<u8>> {
+    let mut flags = if options.is_file { FLAG_FILE } else { 0 };
+    let body = match options.compression_level {
+        Some(level) => {
+            flags |= FLAG_COMPRESSED;
+            compression::deflate(message, level)?
+        }
+        None => message.to_vec()
+    };
+    let body = match options.passphrase {
+        Some(passphrase) => {
+            flags |= FLAG_ENCRYPTED;
+            crypto::seal(&body, passphrase)?
+        }
+        None => body
+    };
+
+    if options.hmac.is_some() {
+        flags |= FLAG_TAGGED;
+    }
+
+    let mut payload = Vec::with_capacity(HEADER_LENGTH + body.len());
+    payload.extend_from_slice(&MAGIC);
+    payload.push(flags);
+    payload.extend_from_slice(&(message.len() as u64).to_be_bytes());
+    payload.extend(body);
+    // the tag goes on last so unpack can check it before touching anything else
+    if let Some((chunk_type, key)) = options.hmac {
+        payload = integrity::append_tag(chunk_type, &payload, key);
+    }
+    trace!("payload packed from {} to {} bytes, flags {:#010b}", message.len(), payload.len(), flags);
+    Ok(payload)
+}
+
+/// Returns the message inside a payload written by `pack`. Messages that
+/// claim or turn out to be larger than `options.max_size` are refused before
+/// they are inflated.
+pub fn unpack(payload: &[u8], options: &UnpackOptions) -> Result<Unpacked> {
+    if !payload.starts_with(&MAGIC) {
+        return Err(PayloadError::MissingHeader.into());
+    }
+    if payload.len() < HEADER_LENGTH {
+        return Err(PayloadError::Truncated.into());
+    }
+    let flags = payload[MAGIC.len()];
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(PayloadError::UnknownFlags(flags).into());
+    }
+    let payload = match (flags & FLAG_TAGGED != 0, options.hmac) {
+        (true, Some((chunk_type, key))) => integrity::verify_tag(chunk_type, payload, key)?,
+        (true, None) => return Err(HmacError::MissingKey.into()),
+        (false, Some(_)) => return Err(HmacError::Untagged.into()),
+        (false, None) => payload
+    };
+    if payload.len() < HEADER_LENGTH {
+        return Err(PayloadError::Truncated.into());
+    }
+    let mut length = [0; 8];
+    length.copy_from_slice(&payload[MAGIC.len() + 1..HEADER_LENGTH]);
+    let length = u64::from_be_bytes(length);
+    if length > options.max_size as u64 {
+        return Err(PayloadError::TooLarge { length, limit: options.max_size }.into());
+    }
+
+    let body = match (flags & FLAG_ENCRYPTED != 0, options.passphrase) {
+        (true, Some(passphrase)) => crypto::open(&payload[HEADER_LENGTH..], passphrase)?,
+        (true, None) => return Err(CryptoError::PassphraseRequired.into()),
+        (false, Some(_)) => return Err(CryptoError::NotEncrypted.into()),
+        (false, None) => payload[HEADER_LENGTH..].to_vec()
+    };
+    let message = if flags & FLAG_COMPRESSED != 0 {
+        compression::inflate(&body, length as usize)?
+    } else {
+        body
+    };
+    if message.len() as u64 != length {
+        return Err(PayloadError::LengthMismatch { expected: length, found: message.len() as u64 }.into());
+    }
+    Ok(Unpacked { message, is_file: flags & FLAG_FILE != 0 })
+}
+
+#[derive(Debug)]
+pub enum PayloadError {
+    MissingHeader,
+    Truncated,
+    UnknownFlags(u8),
+    TooLarge { length: u64, limit: usize },
+    LengthMismatch { expected: u64, found: u64 }
+}
+
+impl std::error::Error for PayloadError {}
+
+impl Display for PayloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PayloadError::MissingHeader => write!(f, "Hidden data has no payload header, it wasn't written by pngme"),
+            PayloadError::Truncated => write!(f, "Payload header ends too early"),
+            PayloadError::UnknownFlags(flags) => write!(f, "Unknown payload flags {:#010b}", flags),
+            PayloadError::TooLarge { length, limit } =>
+                write!(f, "Payload of {} bytes is larger than the {} byte limit", length, limit),
+            PayloadError::LengthMismatch { expected, found } =>
+                write!(f, "Payload should be {} bytes, found {}", expected, found)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::DEFAULT_LEVEL;
+    use crate::Error;
+    use std::str::FromStr;
+
+    fn compressed() -> PackOptions<'static> {
+        PackOptions { compression_level: Some(DEFAULT_LEVEL), ..Default::default() }
+    }
+
+    fn limit(max_size: usize) -> UnpackOptions<'static> {
+        UnpackOptions { max_size, ..Default::default() }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let message = b"compress me, compress me, compress me, compress me".repeat(10);
+        let payload = pack(&message, &compressed()).unwrap();
+
+        assert!(payload.len() < message.len());
+        assert_eq!(payload[MAGIC.len()], FLAG_COMPRESSED);
+        assert_eq!(unpack(&payload, &limit(1024)).unwrap(), Unpacked { message, is_file: false });
+    }
+
+    #[test]
+    fn test_file_flag() {
+        let options = PackOptions { is_file: true, ..compressed() };
+        let payload = pack(b"PMEf looks like an envelope", &options).unwrap();
+        assert_eq!(payload[MAGIC.len()], FLAG_COMPRESSED | FLAG_FILE);
+        assert!(unpack(&payload, &limit(1024)).unwrap().is_file);
+
+        let payload = pack(b"PMEf looks like an envelope", &PackOptions::default()).unwrap();
+        assert!(!unpack(&payload, &limit(1024)).unwrap().is_file);
+    }
+
+    #[test]
+    fn test_plain_message_has_a_header_too() {
+        let payload = pack(b"just a message", &PackOptions::default()).unwrap();
+        assert_eq!(payload[MAGIC.len()], 0);
+        assert_eq!(unpack(&payload, &UnpackOptions::default()).unwrap().message, b"just a message");
+
+        // a message that looks like a header is still just a message
+        let payload = pack(b"PMEpwned", &PackOptions::default()).unwrap();
+        assert_eq!(unpack(&payload, &UnpackOptions::default()).unwrap().message, b"PMEpwned");
+        assert!(matches!(unpack(b"PMEpwned", &UnpackOptions::default()), Err(Error::Payload(PayloadError::Truncated))));
+        assert!(matches!(unpack(b"hello", &UnpackOptions::default()), Err(Error::Payload(PayloadError::MissingHeader))));
+    }
+
+    #[test]
+    fn test_encrypted_flag() {
+        let options = PackOptions { passphrase: Some("secret"), ..compressed() };
+        let payload = pack(b"encrypted message", &options).unwrap();
+        assert_eq!(payload[MAGIC.len()], FLAG_COMPRESSED | FLAG_ENCRYPTED);
+
+        let with_passphrase = UnpackOptions { passphrase: Some("secret"), ..Default::default() };
+        assert_eq!(unpack(&payload, &with_passphrase).unwrap().message, b"encrypted message");
+        assert!(matches!(unpack(&payload, &UnpackOptions::default()), Err(Error::Crypto(CryptoError::PassphraseRequired))));
+
+        let plain = pack(b"plain message", &PackOptions::default()).unwrap();
+        assert!(matches!(unpack(&plain, &with_passphrase), Err(Error::Crypto(CryptoError::NotEncrypted))));
+    }
+
+    #[test]
+    fn test_tagged_flag() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let hmac = Some((&chunk_type, &b"key"[..]));
+        let payload = pack(b"tagged message", &PackOptions { hmac, ..compressed() }).unwrap();
+        assert_eq!(payload[MAGIC.len()], FLAG_COMPRESSED | FLAG_TAGGED);
+
+        let with_key = UnpackOptions { hmac, ..Default::default() };
+        assert_eq!(unpack(&payload, &with_key).unwrap().message, b"tagged message");
+        assert!(matches!(unpack(&payload, &UnpackOptions::default()), Err(Error::Hmac(HmacError::MissingKey))));
+
+        // the tag covers the header, a decoder holding the key notices it was dropped
+        let mut stripped = payload.clone();
+        stripped[MAGIC.len()] &= !FLAG_TAGGED;
+        assert!(matches!(unpack(&stripped, &with_key), Err(Error::Hmac(HmacError::Untagged))));
+    }
+
+    #[test]
+    fn test_message_starting_like_a_tag_is_not_tagged() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let payload = pack(b"PMEhello", &PackOptions::default()).unwrap();
+        assert_eq!(unpack(&payload, &UnpackOptions::default()).unwrap().message, b"PMEhello");
+
+        let with_key = UnpackOptions { hmac: Some((&chunk_type, b"key")), ..Default::default() };
+        assert!(matches!(unpack(&payload, &with_key), Err(Error::Hmac(HmacError::Untagged))));
+    }
+
+    #[test]
+    fn test_size_cap() {
+        let payload = pack(&[0; 4096], &compressed()).unwrap();
+        assert!(matches!(unpack(&payload, &limit(4095)), Err(Error::Payload(PayloadError::TooLarge { length: 4096, .. }))));
+        let payload = pack(&[0; 4096], &PackOptions::default()).unwrap();
+        assert!(matches!(unpack(&payload, &limit(4095)), Err(Error::Payload(PayloadError::TooLarge { length: 4096, .. }))));
+
+        // a header that lies about the length still can't inflate past it
+        let mut lying = pack(&[0; 4096], &compressed()).unwrap();
+        lying[HEADER_LENGTH - 2] = 0;
+        lying[HEADER_LENGTH - 1] = 16;
+        assert!(matches!(unpack(&lying, &limit(4096)), Err(Error::Compression(_))));
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        let mut payload = pack(b"data", &compressed()).unwrap();
+        assert!(matches!(unpack(&payload[..HEADER_LENGTH - 1], &limit(1024)), Err(Error::Payload(PayloadError::Truncated))));
+
+        payload[MAGIC.len()] = 0b1000_0000;
+        assert!(matches!(unpack(&payload, &limit(1024)), Err(Error::Payload(PayloadError::UnknownFlags(0b1000_0000)))));
+    }
+}
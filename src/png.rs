@@ -1,9 +1,20 @@
-use std::convert::{TryFrom, TryInto};
+// the original code here is written long hand, keep it that way
+#![allow(clippy::vec_init_then_push, clippy::iter_cloned_collect)]
+
+use std::convert::TryFrom;
 use std::fmt::Display;
+use std::io::{Read, Write};
 use std::str::FromStr;
 use crate::chunk_type::ChunkType;
 use crate::{Error, Result};
 use crate::chunk::Chunk;
+use crate::ihdr::{Ihdr, IhdrError};
+use crate::ordering::{self, OrderViolation};
+use crate::validate::{self, Finding};
+use crate::reader::PngReader;
+use crate::recover::{self, ParseOptions, ParsedPng};
+use crate::text::TextEntry;
+use crate::writer::PngWriter;
 
 pub trait PngFile {
     const STANDARD_HEADER: [u8; 8];
@@ -30,9 +41,79 @@ impl Png {
         Self { chunks }
     }
 
-     /// Appends a chunk to the end of this `Png` file's `Chunk` list.
+    /// Appends a chunk to this `Png` file's `Chunk` list, just before IEND
+    /// when there is one, since nothing may follow it.
     pub fn append_chunk(&mut self, chunk: Chunk) {
-        self.chunks.push(chunk);
+        match self.chunks.iter().rposition(|x| ordering::is(x.chunk_type(), b"IEND")) {
+            Some(index) => self.chunks.insert(index, chunk),
+            None => self.chunks.push(chunk)
+        }
+    }
+
+    /// Inserts `chunk` at `index`, refusing positions that break the chunk
+    /// ordering rules of the spec.
+    pub fn insert_at(&mut self, index: usize, chunk: Chunk) -> Result<()> {
+        if index > self.chunks.len() {
+            return Err(PngError::InvalidIndex(index).into());
+        }
+
+        let before = self.order_violations();
+        self.chunks.insert(index, chunk);
+        let after = self.order_violations();
+        // indices move with the insert, so violations are counted per type and
+        // reason; one kind showing up more often than before is new
+        let count = |violations: &[OrderViolation], violation: &OrderViolation| violations
+            .iter()
+            .filter(|other| other.chunk_type == violation.chunk_type && other.reason == violation.reason)
+            .count();
+        let new_violation = after.iter().find(|violation| count(&after, violation) > count(&before, violation));
+        if let Some(violation) = new_violation {
+            let message = violation.to_string();
+            self.chunks.remove(index);
+            return Err(PngError::InvalidChunkOrder(message).into());
+        }
+        Ok(())
+    }
+
+    /// Inserts `chunk` right before the first chunk of type `chunk_type`.
+    pub fn insert_chunk_before(&mut self, chunk_type: &str, chunk: Chunk) -> Result<()> {
+        let index = self.position(chunk_type)?;
+        self.insert_at(index, chunk)
+    }
+
+    /// Inserts `chunk` right after the first chunk of type `chunk_type`.
+    pub fn insert_chunk_after(&mut self, chunk_type: &str, chunk: Chunk) -> Result<()> {
+        let index = self.position(chunk_type)?;
+        self.insert_at(index + 1, chunk)
+    }
+
+    /// Checks the chunk order against the spec: IHDR first, PLTE before IDAT,
+    /// IDATs next to each other, IEND last and so on.
+    pub fn check_order(&self) -> Result<()> {
+        match self.order_violations().first() {
+            Some(violation) => Err(PngError::InvalidChunkOrder(violation.to_string()).into()),
+            None => Ok(())
+        }
+    }
+
+    /// Checks the whole png against the spec and returns every problem found
+    /// instead of stopping at the first one. Offsets are those the chunks get
+    /// when this `Png` is written out.
+    pub fn validate(&self) -> Vec<Finding> {
+        validate::validate_png(self)
+    }
+
+    fn order_violations(&self) -> Vec<OrderViolation> {
+        let chunk_types: Vec<&ChunkType> = self.chunks.iter().map(|x| x.chunk_type()).collect();
+        ordering::order_violations(&chunk_types)
+    }
+
+    fn position(&self, chunk_type: &str) -> Result<usize> {
+        let wanted_chunk_type = ChunkType::from_str(chunk_type)?;
+        Ok(self.chunks
+            .iter()
+            .position(|x| *x.chunk_type() == wanted_chunk_type)
+            .ok_or(PngError::NotFoundChunk)?)
     }
 
     /// Searches for a `Chunk` with the specified `chunk_type` and removes the first
@@ -47,6 +128,39 @@ impl Png {
         Ok (self.chunks.remove(index))
     }
 
+    /// Reads a whole `Png` from `reader` chunk by chunk.
+    /// The first chunk has to be a valid IHDR.
+    pub fn read_from<R: Read>(reader: R) -> Result<Self> {
+        let chunks = PngReader::new(reader)?.collect::<Result<Vec<Chunk>>>()?;
+        let png = Png { chunks };
+        png.ihdr()?;
+        Ok(png)
+    }
+
+    /// Parses `bytes` as a png. Strict options behave like `Png::try_from`,
+    /// lenient ones salvage what they can and never fail.
+    pub fn parse(bytes: &[u8], options: &ParseOptions) -> Result<ParsedPng> {
+        if options.lenient {
+            return Ok(recover::recover(bytes, options));
+        }
+        Ok(ParsedPng {
+            png: Png::try_from(bytes)?,
+            findings: Vec::new(),
+            dropped: Vec::new()
+        })
+    }
+
+    /// Parses the IHDR, which must be the first chunk.
+    pub fn ihdr(&self) -> Result<Ihdr> {
+        let first = self.chunks.first().ok_or(IhdrError::MissingIhdr)?;
+        Ihdr::try_from(first)
+    }
+
+    /// Every tEXt, zTXt and iTXt chunk, decoded, in file order.
+    pub fn text_entries(&self) -> Result<Vec<TextEntry>> {
+        self.chunks.iter().filter_map(TextEntry::from_chunk).collect()
+    }
+
     /// The header of this PNG.
     pub fn header(&self) -> &[u8; 8] {
         &Png::STANDARD_HEADER
@@ -75,60 +189,59 @@ impl Png {
         }
     }
 
+    /// Every `Chunk` with the specified `chunk_type`, in file order.
+    pub fn chunks_by_type(&self, chunk_type: &str) -> Vec<&Chunk> {
+        match ChunkType::from_str(chunk_type) {
+            Ok(wanted_chunk_type) => self.chunks
+                .iter()
+                .filter(|chunk| *chunk.chunk_type() == wanted_chunk_type)
+                .collect(),
+            Err(_) => Vec::new()
+        }
+    }
+
     /// Returns this `Png` as a byte sequence.
     /// These bytes will contain the header followed by the bytes of all of the chunks.
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut result: Vec<u8> = Vec::with_capacity(self.chunks.len() * 36 + 1);
-        result.extend(Png::STANDARD_HEADER.iter().cloned());
+        let length = Png::STANDARD_HEADER.len() + self.chunks
+            .iter()
+            .map(|chunk| chunk.length() as usize + Chunk::METADATA_LENGTH)
+            .sum::<usize>();
+        let mut result: Vec<u8> = Vec::with_capacity(length);
+        self.write_to(&mut result).expect("writing to a Vec never fails");
+        result
+    }
+
+    /// Streams the header and every chunk to `writer`, then flushes it.
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+        let mut png_writer = PngWriter::new(writer)?;
         for chunk in &self.chunks {
-            result.append(&mut chunk.as_bytes());
+            png_writer.write_chunk(chunk)?;
         }
-        result
+        png_writer.finish()?;
+        Ok(())
     }
 }
 
 pub fn check_header(value: &[u8]) -> bool {
-    for i in 0..8 {
-        if value[i] != Png::STANDARD_HEADER[i] {
-            return false;
-        }
-    }
-    true
+    value.starts_with(&Png::STANDARD_HEADER)
 }
 
 impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self> {
-        if value.len() < Self::STANDARD_HEADER.len() {
-            return Err(Box::new(PngError::InvalidLength));
-        }
-        if !check_header(value) {
-            return Err(Box::new(PngError::InvalidHeader));
-        }
-        let mut chunks: Vec<Chunk> = Vec::new();
-        let mut index = 8; // starting index
-        while index < value.len() {
-            let data_length = u32::from_be_bytes(value[index..index+4].try_into().unwrap());
-            let chunk_length: usize = 12 + data_length as usize;
-            let bytes = &value[index..index + chunk_length];
-            println!("{:?}", bytes);
-            let chunk = Chunk::try_from(bytes)?;
-            index += chunk_length;
-            chunks.push(chunk);
-        }
-
-        Ok(Png {
-            chunks: chunks
-        })
+        Png::read_from(value)
     }
 }
 
 #[derive(Debug)]
-enum PngError {
+pub enum PngError {
     InvalidLength,
     InvalidHeader,
-    NotFoundChunk
+    NotFoundChunk,
+    InvalidIndex(usize),
+    InvalidChunkOrder(String)
 }
 
 impl std::error::Error for PngError {}
@@ -138,7 +251,9 @@ impl Display for PngError {
         match self {
             PngError::InvalidLength => write!(f, "Invalid lengths"),
             PngError::InvalidHeader => write!(f, "Invalid header"),
-            PngError::NotFoundChunk => write!(f, "Chunk is not fond")
+            PngError::NotFoundChunk => write!(f, "Chunk is not found"),
+            PngError::InvalidIndex(index) => write!(f, "Index {} is out of bounds", index),
+            PngError::InvalidChunkOrder(violation) => write!(f, "Invalid chunk order, {}", violation)
         }
     }
 }
@@ -147,7 +262,10 @@ impl Display for PngError {
 mod tests {
     use super::*;
     use crate::chunk_type::ChunkType;
-    use crate::chunk::Chunk;
+    use crate::chunk::{Chunk, ChunkError};
+    use crate::ihdr::{ColorType, InterlaceMethod};
+    use crate::image::Image;
+    use crate::text::{CompressedTextChunk, InternationalTextChunk, TextChunk};
     use std::convert::TryFrom;
     
     fn testing_chunks() -> Vec<Chunk> {
@@ -160,6 +278,19 @@ mod tests {
         chunks
     }
 
+    fn with_ihdr(chunks: Vec<Chunk>) -> Vec<Chunk> {
+        let ihdr = Ihdr {
+            width: 1,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: InterlaceMethod::None
+        };
+        std::iter::once(ihdr.to_chunk()).chain(chunks).collect()
+    }
+
     fn testing_png() -> Png {
         let chunks = testing_chunks();
         Png::from_chunks(chunks)
@@ -182,7 +313,7 @@ mod tests {
 
     #[test]
     fn test_valid_from_bytes() {
-        let chunk_bytes: Vec<u8> = testing_chunks()
+        let chunk_bytes: Vec<u8> = with_ihdr(testing_chunks())
             .into_iter()
             .flat_map(|chunk| chunk.as_bytes())
             .collect();
@@ -198,6 +329,28 @@ mod tests {
         assert!(png.is_ok());
     }
 
+    #[test]
+    fn test_first_chunk_must_be_ihdr() {
+        let bytes = testing_png().as_bytes();
+        assert!(matches!(Png::try_from(bytes.as_ref()), Err(Error::Ihdr(IhdrError::NotIhdr(_)))));
+
+        let bytes = Png::from_chunks(Vec::new()).as_bytes();
+        assert!(matches!(Png::try_from(bytes.as_ref()), Err(Error::Ihdr(IhdrError::MissingIhdr))));
+
+        let mut chunks = with_ihdr(testing_chunks());
+        chunks[0] = Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 13]);
+        let bytes = Png::from_chunks(chunks).as_bytes();
+        assert!(matches!(Png::try_from(bytes.as_ref()), Err(Error::Ihdr(IhdrError::InvalidDimensions { .. }))));
+    }
+
+    #[test]
+    fn test_ihdr() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.ihdr().unwrap();
+        assert_eq!((ihdr.width, ihdr.height), (50, 50));
+        assert_eq!(ihdr.color_type, ColorType::Rgba);
+    }
+
     #[test]
     fn test_invalid_header() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -263,6 +416,69 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_append_chunk_goes_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let chunks = png.chunks();
+
+        assert_eq!(&chunks[chunks.len() - 2].chunk_type().to_string(), "TeSt");
+        assert_eq!(&chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+        assert!(png.check_order().is_ok());
+    }
+
+    #[test]
+    fn test_insert_chunk_before_and_after() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk_after("IHDR", chunk_from_strings("frSt", "after header").unwrap()).unwrap();
+        png.insert_chunk_before("IDAT", chunk_from_strings("laSt", "before data").unwrap()).unwrap();
+        let chunks = png.chunks();
+        let idat = chunks.iter().position(|chunk| chunk.chunk_type().to_string() == "IDAT").unwrap();
+
+        assert_eq!(&chunks[1].chunk_type().to_string(), "frSt");
+        assert_eq!(&chunks[idat - 1].chunk_type().to_string(), "laSt");
+        assert!(png.insert_chunk_after("noNe", chunk_from_strings("TeSt", "x").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_insert_at_rejects_invalid_order() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let length = png.chunks().len();
+
+        assert!(png.insert_at(length + 1, chunk_from_strings("TeSt", "x").unwrap()).is_err());
+        assert!(png.insert_at(0, chunk_from_strings("TeSt", "x").unwrap()).is_err());
+        assert!(png.insert_at(length, chunk_from_strings("TeSt", "x").unwrap()).is_err());
+        assert!(png.insert_at(1, chunk_from_strings("IHDR", "x").unwrap()).is_err());
+        assert_eq!(png.chunks().len(), length);
+
+        png.insert_at(1, chunk_from_strings("TeSt", "x").unwrap()).unwrap();
+        assert_eq!(png.chunks().len(), length + 1);
+    }
+
+    #[test]
+    fn test_insert_at_rejects_repeated_violations() {
+        let names = ["IHDR", "IDAT", "tEXt", "IDAT", "zTXt", "IEND"];
+        let chunks = names.iter().map(|name| chunk_from_strings(name, "").unwrap()).collect();
+        let mut png = Png::from_chunks(chunks);
+
+        // already one IDAT out of line, a second one in another gap is still new
+        assert!(png.insert_at(5, chunk_from_strings("IDAT", "").unwrap()).is_err());
+        assert_eq!(png.chunks().len(), names.len());
+        png.insert_at(2, chunk_from_strings("IDAT", "").unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_insert_at_rejects_trading_violations() {
+        let names = ["IHDR", "gAMA", "tRNS", "PLTE", "IDAT", "IEND"];
+        let chunks = names.iter().map(|name| chunk_from_strings(name, "").unwrap()).collect();
+        let mut png = Png::from_chunks(chunks);
+
+        // fixes tRNS coming before PLTE but puts gAMA after it
+        let result = png.insert_at(1, chunk_from_strings("PLTE", "").unwrap());
+        assert!(matches!(result, Err(Error::Png(PngError::InvalidChunkOrder(message))) if message.starts_with("gAMA")));
+        assert_eq!(png.chunks().len(), names.len());
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -288,7 +504,7 @@ mod tests {
 
     #[test]
     fn test_png_trait_impls() {
-        let chunk_bytes: Vec<u8> = testing_chunks()
+        let chunk_bytes: Vec<u8> = with_ihdr(testing_chunks())
             .into_iter()
             .flat_map(|chunk| chunk.as_bytes())
             .collect();
@@ -303,6 +519,72 @@ mod tests {
 
         let _png_string = format!("{}", png);
     }
+
+    #[test]
+    fn test_check_header_short_input() {
+        assert!(!check_header(&Png::STANDARD_HEADER[..4]));
+        assert!(!check_header(&[]));
+    }
+
+    #[test]
+    fn test_truncated_file_does_not_panic() {
+        // cutting exactly between two chunks still gives a parsable file,
+        // everything else must be an error rather than a panic
+        for end in 0..PNG_FILE.len() {
+            let result = Png::try_from(&PNG_FILE[..end]);
+            if end < Png::STANDARD_HEADER.len() {
+                assert!(result.is_err());
+            }
+        }
+        assert!(Png::try_from(&PNG_FILE[..PNG_FILE.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_truncated_chunk_reports_offset() {
+        let png = testing_png();
+        let bytes = png.as_bytes();
+        let last_chunk_start = bytes.len() - png.chunks()[2].as_bytes().len();
+
+        match Png::try_from(&bytes[..bytes.len() - 1]) {
+            Err(Error::Chunk(ChunkError::TruncatedChunk { offset, needed, available })) => {
+                assert_eq!(offset, last_chunk_start);
+                assert_eq!(needed, available + 1);
+            }
+            other => panic!("expected truncated chunk, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a second middle chunk").unwrap());
+
+        let chunks = png.chunks_by_type("miDl");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].data_as_string().unwrap(), "I am a second middle chunk");
+        assert!(png.chunks_by_type("nOpE").is_empty());
+        assert!(png.chunks_by_type("12345").is_empty());
+    }
+
+    #[test]
+    fn test_decode_image() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = Image::try_from(&png).unwrap();
+        assert_eq!((image.width, image.height), (50, 50));
+        assert_eq!(image.pixels.len(), 50 * 50 * 4);
+    }
+
+    #[test]
+    fn test_text_entries() {
+        let mut png = Png::from_chunks(with_ihdr(testing_chunks()));
+        png.append_chunk(TextChunk::new("Author", "Renée").unwrap().to_chunk());
+        png.append_chunk(CompressedTextChunk::new("Comment", "squeezed").unwrap().to_chunk());
+        png.append_chunk(InternationalTextChunk::new("Title", "ja", "題名", "日本", true).unwrap().to_chunk());
+
+        let entries = png.text_entries().unwrap();
+        let pairs: Vec<(&str, &str)> = entries.iter().map(|entry| (entry.keyword(), entry.text())).collect();
+        assert_eq!(pairs, vec![("Author", "Renée"), ("Comment", "squeezed"), ("Title", "日本")]);
+    }
     
 
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
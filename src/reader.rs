@@ -0,0 +1,205 @@
+use std::convert::TryFrom;
+use std::io::{ErrorKind, Read};
+
+use crate::chunk::{Chunk, ChunkError};
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, PngError, PngFile};
+use crate::Result;
+
+/// Reads a png one `Chunk` at a time from any `Read`, so big files or stdin
+/// never have to be held in memory as a whole.
+pub struct PngReader<R: Read> {
+    reader: R,
+    offset: usize,
+    finished: bool
+}
+
+impl<R: Read> PngReader<R> {
+    /// Reads and checks the png signature, leaving the reader at the first chunk.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0; 8];
+        if read_up_to(&mut reader, &mut header)? < header.len() {
+            return Err(PngError::InvalidLength.into());
+        }
+        if header != Png::STANDARD_HEADER {
+            return Err(PngError::InvalidHeader.into());
+        }
+
+        Ok(PngReader {
+            reader,
+            offset: header.len(),
+            finished: false
+        })
+    }
+
+    /// Byte offset of the next chunk from the start of the file.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<Chunk>> {
+        let start = self.offset;
+        let mut metadata = [0; 8]; // length and type
+        let read = read_up_to(&mut self.reader, &mut metadata)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if read < metadata.len() {
+            return Err(truncated(start, Chunk::METADATA_LENGTH, read));
+        }
+
+        let length = u32::from_be_bytes([metadata[0], metadata[1], metadata[2], metadata[3]]);
+        let chunk_length = (length as usize)
+            .checked_add(Chunk::METADATA_LENGTH)
+            .ok_or(ChunkError::InvalidDataLength)?;
+        trace!("chunk offset={} length={}", start, chunk_length);
+
+        // grows as bytes arrive, so a bogus length can't make us allocate gigabytes up front
+        let mut data = Vec::new();
+        (&mut self.reader).take(length as u64).read_to_end(&mut data)?;
+        let mut crc = [0; 4];
+        let crc_read = read_up_to(&mut self.reader, &mut crc)?;
+        if data.len() < length as usize || crc_read < crc.len() {
+            return Err(truncated(start, chunk_length, metadata.len() + data.len() + crc_read));
+        }
+
+        let chunk_type = ChunkType::try_from([metadata[4], metadata[5], metadata[6], metadata[7]])?;
+        let chunk = Chunk::new(chunk_type, data);
+        let provided_crc = u32::from_be_bytes(crc);
+        if chunk.crc() != provided_crc {
+            trace!("crc mismatch offset={} type={} expected={:08x} provided={:08x}",
+                start, chunk.chunk_type(), chunk.crc(), provided_crc);
+            return Err(ChunkError::InvalidCrc.into());
+        }
+
+        self.offset += chunk_length;
+        Ok(Some(chunk))
+    }
+}
+
+impl<R: Read> Iterator for PngReader<R> {
+    type Item = Result<Chunk>;
+
+    // stops for good after the first error, the stream position is unknown by then
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.read_chunk() {
+            Ok(Some(chunk)) => Some(Ok(chunk)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(error) => {
+                self.finished = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+// like read_exact, but reports how much was read instead of failing at eof
+fn read_up_to<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error.into())
+        }
+    }
+    Ok(filled)
+}
+
+fn truncated(offset: usize, needed: usize, available: usize) -> crate::Error {
+    ChunkError::TruncatedChunk { offset, needed, available }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use std::str::FromStr;
+
+    // hands out at most one byte per read call
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() || buffer.is_empty() {
+                return Ok(0);
+            }
+            buffer[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"I am the first chunk".to_vec()),
+            Chunk::new(ChunkType::from_str("LASt").unwrap(), b"I am the last chunk".to_vec()),
+        ];
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_reads_chunks_in_order() {
+        let bytes = testing_bytes();
+        let chunks: Vec<Chunk> = PngReader::new(bytes.as_slice()).unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].chunk_type().to_string(), "FrSt");
+        assert_eq!(chunks[1].data_as_string().unwrap(), "I am the last chunk");
+    }
+
+    #[test]
+    fn test_short_reads() {
+        let bytes = testing_bytes();
+        let chunks: Vec<Chunk> = PngReader::new(Trickle(&bytes)).unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(chunks.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let mut bytes = testing_bytes();
+        bytes[0] = 13;
+
+        assert!(matches!(PngReader::new(bytes.as_slice()), Err(Error::Png(PngError::InvalidHeader))));
+        assert!(matches!(PngReader::new(&bytes[..4]), Err(Error::Png(PngError::InvalidLength))));
+    }
+
+    #[test]
+    fn test_truncated_stream_stops_after_error() {
+        let bytes = testing_bytes();
+        let mut reader = PngReader::new(&bytes[..bytes.len() - 2]).unwrap();
+
+        assert!(reader.next().unwrap().is_ok());
+        match reader.next() {
+            Some(Err(Error::Chunk(ChunkError::TruncatedChunk { offset, needed, available }))) => {
+                assert_eq!(offset, 8 + 12 + 20);
+                assert_eq!(needed, 12 + 19);
+                assert_eq!(available, needed - 2);
+            }
+            other => panic!("expected truncated chunk, got {:?}", other)
+        }
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_offset_tracks_chunks() {
+        let bytes = testing_bytes();
+        let mut reader = PngReader::new(bytes.as_slice()).unwrap();
+
+        assert_eq!(reader.offset(), 8);
+        reader.next().unwrap().unwrap();
+        assert_eq!(reader.offset(), 8 + 12 + 20);
+    }
+}
@@ -0,0 +1,245 @@
+use std::convert::TryFrom;
+use std::ops::Range;
+
+use crate::chunk::{be_u32, Chunk};
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, PngFile};
+use crate::validate::{finding, Finding, Severity};
+
+// types we trust enough to resync on even when their crc is off
+const KNOWN_TYPES: [&[u8; 4]; 21] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"bKGD",
+    b"hIST", b"tRNS", b"pHYs", b"sPLT", b"tIME", b"tEXt", b"zTXt", b"iTXt", b"eXIf", b"acTL"
+];
+const MAX_CHUNK_LENGTH: usize = i32::MAX as usize;
+
+/// How forgiving `Png::parse` is with damaged files.
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    /// Record problems instead of failing and skip over damaged bytes.
+    pub lenient: bool,
+    /// In lenient mode, keep chunks whose only problem is a wrong crc.
+    pub keep_bad_crc: bool
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions { lenient: false, keep_bad_crc: true }
+    }
+}
+
+impl ParseOptions {
+    pub fn lenient() -> Self {
+        ParseOptions { lenient: true, ..Default::default() }
+    }
+}
+
+/// What `Png::parse` got out of a file: the chunks it could keep, what was
+/// wrong with the rest and which byte ranges were thrown away.
+#[derive(Debug)]
+pub struct ParsedPng {
+    pub png: Png,
+    pub findings: Vec<Finding>,
+    pub dropped: Vec<Range<usize>>
+}
+
+/// Salvages every chunk it can from `bytes`. Chunks with a wrong crc are kept
+/// (if allowed) when their length lines up with the next chunk, anything else
+/// is skipped up to the next position that looks like a real chunk.
+pub fn recover(bytes: &[u8], options: &ParseOptions) -> ParsedPng {
+    let mut chunks = Vec::new();
+    let mut findings = Vec::new();
+    let mut dropped = Vec::new();
+
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        findings.push(finding(Severity::Warning, 0, None, String::from("png signature is damaged")));
+    }
+    let mut offset = Png::STANDARD_HEADER.len().min(bytes.len());
+
+    while offset < bytes.len() {
+        let found = candidate(bytes, offset).map(|candidate| {
+            let crc_ok = candidate.crc_ok(bytes);
+            (candidate, crc_ok)
+        });
+        match found {
+            Some((candidate, crc_ok)) if crc_ok || (options.keep_bad_crc && lines_up(bytes, candidate.end)) => {
+                if !crc_ok {
+                    findings.push(finding(Severity::Warning, offset, Some(&candidate.chunk_type),
+                        String::from("crc mismatch, chunk kept")));
+                }
+                // a kept chunk shows the crc from the file, not one that matches
+                let data = bytes[offset + 8..candidate.end - 4].to_vec();
+                chunks.push(Chunk::from_parts(candidate.chunk_type, data, candidate.stored_crc(bytes)));
+                offset = candidate.end;
+            }
+            other => {
+                let message = match other {
+                    Some((candidate, _)) => format!("crc mismatch in {}, chunk dropped", candidate.chunk_type),
+                    None if bytes.len() - offset < Chunk::METADATA_LENGTH =>
+                        String::from("truncated chunk"),
+                    None => String::from("no valid chunk here")
+                };
+                let next = resync(bytes, offset + 1);
+                trace!("resync from={} to={}", offset, next);
+                findings.push(finding(Severity::Error, offset, None,
+                    format!("{}, dropped {} bytes", message, next - offset)));
+                dropped.push(offset..next);
+                offset = next;
+            }
+        }
+    }
+
+    ParsedPng { png: Png::from_chunks(chunks), findings, dropped }
+}
+
+struct Candidate {
+    chunk_type: ChunkType,
+    offset: usize,
+    end: usize
+}
+
+impl Candidate {
+    fn stored_crc(&self, bytes: &[u8]) -> u32 {
+        be_u32(&bytes[self.end - 4..])
+    }
+
+    // the expensive part, kept out of `candidate` so scanning stays linear
+    fn crc_ok(&self, bytes: &[u8]) -> bool {
+        crc::crc32::checksum_ieee(&bytes[self.offset + 4..self.end - 4]) == self.stored_crc(bytes)
+    }
+}
+
+// a chunk header at `offset` with a letter type and a length that fits the buffer
+fn candidate(bytes: &[u8], offset: usize) -> Option<Candidate> {
+    if bytes.len() - offset < Chunk::METADATA_LENGTH {
+        return None;
+    }
+    let type_bytes = [bytes[offset + 4], bytes[offset + 5], bytes[offset + 6], bytes[offset + 7]];
+    if !type_bytes.iter().all(|byte| ChunkType::is_valid_byte(*byte)) {
+        return None;
+    }
+    let length = be_u32(&bytes[offset..]) as usize;
+    if length > MAX_CHUNK_LENGTH || length + Chunk::METADATA_LENGTH > bytes.len() - offset {
+        return None;
+    }
+
+    Some(Candidate {
+        chunk_type: ChunkType::try_from(type_bytes).ok()?,
+        offset,
+        end: offset + length + Chunk::METADATA_LENGTH
+    })
+}
+
+// true when `offset` is the end of the data or the start of something chunk shaped,
+// which tells us a length field is probably right even though the crc is not
+fn lines_up(bytes: &[u8], offset: usize) -> bool {
+    offset == bytes.len() || candidate(bytes, offset).is_some()
+}
+
+// the first offset from `from` where a chunk with a valid crc, or a well known
+// type, starts; the end of the buffer if there is none
+pub(crate) fn resync(bytes: &[u8], from: usize) -> usize {
+    (from..bytes.len())
+        .find(|offset| match candidate(bytes, *offset) {
+            Some(candidate) => KNOWN_TYPES.iter().any(|name| candidate.chunk_type.bytes() == **name)
+                || candidate.crc_ok(bytes),
+            None => false
+        })
+        .unwrap_or(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.as_bytes().to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("FrSt", "I am the first chunk"),
+            chunk("miDl", "I am another chunk"),
+            chunk("LASt", "I am the last chunk"),
+        ])
+    }
+
+    fn types(parsed: &ParsedPng) -> Vec<String> {
+        parsed.png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let parsed = recover(&testing_png().as_bytes(), &ParseOptions::lenient());
+        assert_eq!(types(&parsed), vec!["FrSt", "miDl", "LASt"]);
+        assert!(parsed.findings.is_empty());
+        assert!(parsed.dropped.is_empty());
+    }
+
+    #[test]
+    fn test_bad_crc_is_kept() {
+        let mut bytes = testing_png().as_bytes();
+        let crc_offset = 8 + 32 + 30 - 1;
+        bytes[crc_offset] ^= 0xff;
+        let parsed = recover(&bytes, &ParseOptions::lenient());
+
+        assert_eq!(types(&parsed), vec!["FrSt", "miDl", "LASt"]);
+        assert_eq!(parsed.findings.len(), 1);
+        assert_eq!(parsed.findings[0].offset, 8 + 32);
+        assert_eq!(parsed.png.chunks()[1].data_as_string().unwrap(), "I am another chunk");
+        assert_eq!(parsed.png.chunks()[1].crc(), be_u32(&bytes[crc_offset - 3..]));
+    }
+
+    #[test]
+    fn test_bad_crc_dropped_when_not_kept() {
+        let mut bytes = testing_png().as_bytes();
+        bytes[8 + 32 + 30 - 1] ^= 0xff;
+        let options = ParseOptions { keep_bad_crc: false, ..ParseOptions::lenient() };
+        let parsed = recover(&bytes, &options);
+
+        assert_eq!(types(&parsed), vec!["FrSt", "LASt"]);
+        assert_eq!(parsed.dropped, vec![40..70]);
+    }
+
+    #[test]
+    fn test_resync_after_bad_length() {
+        let mut bytes = testing_png().as_bytes();
+        bytes[8 + 32] = 0x7f; // length of the middle chunk is now huge
+        let parsed = recover(&bytes, &ParseOptions::lenient());
+
+        assert_eq!(types(&parsed), vec!["FrSt", "LASt"]);
+        assert_eq!(parsed.dropped, vec![40..70]);
+        assert_eq!(parsed.findings[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn test_garbage_between_chunks() {
+        let png = testing_png();
+        let mut bytes = png.as_bytes();
+        let garbage = [0xde, 0xad, 0xbe, 0xef, 1, 2, 3];
+        bytes.splice(40..40, garbage.iter().copied());
+        let parsed = recover(&bytes, &ParseOptions::lenient());
+
+        assert_eq!(types(&parsed), vec!["FrSt", "miDl", "LASt"]);
+        assert_eq!(parsed.dropped, vec![40..47]);
+    }
+
+    #[test]
+    fn test_truncated_tail() {
+        let bytes = testing_png().as_bytes();
+        let parsed = recover(&bytes[..bytes.len() - 5], &ParseOptions::lenient());
+
+        assert_eq!(types(&parsed), vec!["FrSt", "miDl"]);
+        assert_eq!(parsed.dropped, vec![70..bytes.len() - 5]);
+    }
+
+    #[test]
+    fn test_parse_strict_fails() {
+        let mut bytes = testing_png().as_bytes();
+        bytes[8 + 32 + 30 - 1] ^= 0xff;
+
+        assert!(Png::parse(&bytes, &ParseOptions::default()).is_err());
+        assert!(Png::parse(&bytes, &ParseOptions::lenient()).is_ok());
+    }
+}
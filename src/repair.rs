@@ -0,0 +1,144 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::chunk::{be_u32, compute_crc, Chunk};
+use crate::chunk_ref::at_offset;
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, PngError, PngFile};
+use crate::Result;
+
+/// A chunk whose stored crc didn't match its type and data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrcRepair {
+    /// Byte offset of the chunk from the start of the file.
+    pub offset: usize,
+    pub chunk_type: ChunkType,
+    pub stored: u32,
+    pub computed: u32
+}
+
+impl Display for CrcRepair {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at byte {}: crc {:08x} -> {:08x}",
+            self.chunk_type, self.offset, self.stored, self.computed)
+    }
+}
+
+/// Recomputes the crc of every chunk in `bytes` and overwrites the ones that
+/// are wrong, leaving everything else untouched. Chunk lengths and types must
+/// still be intact, a file that is damaged beyond its crcs is an error.
+pub fn repair_crcs(bytes: &mut [u8]) -> Result<Vec<CrcRepair>> {
+    let repairs = find_bad_crcs(bytes)?;
+    for (crc_offset, repair) in &repairs {
+        bytes[*crc_offset..*crc_offset + 4].copy_from_slice(&repair.computed.to_be_bytes());
+    }
+    Ok(repairs.into_iter().map(|(_, repair)| repair).collect())
+}
+
+/// Same as `repair_crcs` but for a file on disk, only the 4 bytes of each bad
+/// crc are written back.
+pub fn repair_file<P: AsRef<Path>>(path: P) -> Result<Vec<CrcRepair>> {
+    let repairs = find_bad_crcs(&fs::read(&path)?)?;
+    if !repairs.is_empty() {
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        for (crc_offset, repair) in &repairs {
+            file.seek(SeekFrom::Start(*crc_offset as u64))?;
+            file.write_all(&repair.computed.to_be_bytes())?;
+        }
+        file.flush()?;
+    }
+    Ok(repairs.into_iter().map(|(_, repair)| repair).collect())
+}
+
+// every chunk with a wrong crc, paired with the offset of its crc field
+fn find_bad_crcs(bytes: &[u8]) -> Result<Vec<(usize, CrcRepair)>> {
+    if bytes.len() < Png::STANDARD_HEADER.len() {
+        return Err(PngError::InvalidLength.into());
+    }
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        return Err(PngError::InvalidHeader.into());
+    }
+
+    let mut repairs = Vec::new();
+    let mut offset = Png::STANDARD_HEADER.len();
+    while offset < bytes.len() {
+        let chunk_length = Chunk::encoded_length(&bytes[offset..]).map_err(|error| at_offset(error, offset))?;
+        let chunk_type = ChunkType::try_from([bytes[offset + 4], bytes[offset + 5], bytes[offset + 6], bytes[offset + 7]])?;
+        let crc_offset = offset + chunk_length - 4;
+
+        let stored = be_u32(&bytes[crc_offset..]);
+        let computed = compute_crc(&chunk_type, &bytes[offset + 8..crc_offset]);
+        if stored != computed {
+            trace!("bad crc type={} offset={} stored={:08x} computed={:08x}", chunk_type, offset, stored, computed);
+            repairs.push((crc_offset, CrcRepair { offset, chunk_type, stored, computed }));
+        }
+        offset += chunk_length;
+    }
+    Ok(repairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"I am the first chunk".to_vec()),
+            Chunk::new(ChunkType::from_str("miDl").unwrap(), b"I am another chunk".to_vec()),
+            Chunk::new(ChunkType::from_str("LASt").unwrap(), b"I am the last chunk".to_vec()),
+        ])
+    }
+
+    #[test]
+    fn test_repair_crcs() {
+        let original = testing_png().as_bytes();
+        let mut bytes = original.clone();
+        bytes[8 + 32 + 30 - 1] ^= 0xff;
+        let repairs = repair_crcs(&mut bytes).unwrap();
+
+        assert_eq!(repairs.len(), 1);
+        assert_eq!(repairs[0].offset, 8 + 32);
+        assert_eq!(repairs[0].chunk_type.to_string(), "miDl");
+        assert_ne!(repairs[0].stored, repairs[0].computed);
+        assert_eq!(bytes, original);
+    }
+
+    #[test]
+    fn test_repair_crcs_clean_file() {
+        let original = testing_png().as_bytes();
+        let mut bytes = original.clone();
+
+        assert!(repair_crcs(&mut bytes).unwrap().is_empty());
+        assert_eq!(bytes, original);
+    }
+
+    #[test]
+    fn test_repair_crcs_truncated() {
+        let bytes = testing_png().as_bytes();
+        let mut bytes = bytes[..bytes.len() - 2].to_vec();
+
+        assert!(repair_crcs(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn test_repair_file() {
+        let path = std::env::temp_dir().join(format!("pngme-repair-{}.png", std::process::id()));
+        let original = testing_png().as_bytes();
+        let mut bytes = original.clone();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+
+        let repairs = repair_file(&path).unwrap();
+        let repaired = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(repairs.len(), 1);
+        assert_eq!(repairs[0].chunk_type.to_string(), "LASt");
+        assert_eq!(repaired, original);
+    }
+}
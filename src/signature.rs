@@ -0,0 +1,324 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ordering;
+use crate::png::Png;
+use crate::Result;
+
+/// Ancillary, private and not safe to copy: an editor that changes the image
+/// without knowing this chunk should drop it rather than keep a stale signature.
+pub const CHUNK_TYPE: [u8; 4] = *b"siGN";
+pub const VERSION: u8 = 1;
+const KEY_LENGTH: usize = 32;
+const SIGNATURE_LENGTH: usize = 64;
+
+/// Contents of the signature chunk: version, signer's public key, the count
+/// and types of the ancillary chunks that are covered, then the signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureChunk {
+    pub public_key: [u8; KEY_LENGTH],
+    pub ancillary: Vec<ChunkType>,
+    pub signature: [u8; SIGNATURE_LENGTH]
+}
+
+impl TryFrom<&Chunk> for SignatureChunk {
+    type Error = crate::Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        let data = chunk.data();
+        if data.len() < 2 + KEY_LENGTH + SIGNATURE_LENGTH {
+            return Err(SignatureError::Malformed.into());
+        }
+        if data[0] != VERSION {
+            return Err(SignatureError::UnsupportedVersion(data[0]).into());
+        }
+        let count = data[1 + KEY_LENGTH] as usize;
+        let types_start = 2 + KEY_LENGTH;
+        if data.len() != types_start + count * 4 + SIGNATURE_LENGTH {
+            return Err(SignatureError::Malformed.into());
+        }
+
+        let mut public_key = [0; KEY_LENGTH];
+        public_key.copy_from_slice(&data[1..1 + KEY_LENGTH]);
+        let ancillary = data[types_start..types_start + count * 4]
+            .chunks(4)
+            .map(|name| ChunkType::try_from([name[0], name[1], name[2], name[3]]))
+            .collect::<Result<Vec<_>>>()?;
+        let mut signature = [0; SIGNATURE_LENGTH];
+        signature.copy_from_slice(&data[data.len() - SIGNATURE_LENGTH..]);
+        Ok(SignatureChunk { public_key, ancillary, signature })
+    }
+}
+
+impl SignatureChunk {
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::try_from(CHUNK_TYPE).unwrap(), self.to_bytes())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.signed_prefix();
+        bytes.extend_from_slice(&self.signature);
+        bytes
+    }
+
+    // everything in front of the signature, which is signed along with the
+    // digest so the list of covered chunks can't be edited
+    fn signed_prefix(&self) -> Vec<u8> {
+        let mut bytes = vec![VERSION];
+        bytes.extend_from_slice(&self.public_key);
+        bytes.push(self.ancillary.len() as u8);
+        for chunk_type in &self.ancillary {
+            bytes.extend_from_slice(&chunk_type.bytes());
+        }
+        bytes
+    }
+}
+
+/// Indices of the chunks a signature covers: every critical chunk plus the
+/// ancillary chunks whose type is listed, never the signature chunk itself.
+pub fn covered_chunks(png: &Png, ancillary: &[ChunkType]) -> Vec<usize> {
+    png.chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, chunk)| {
+            let chunk_type = chunk.chunk_type();
+            !ordering::is(chunk_type, &CHUNK_TYPE)
+                && (chunk_type.is_critical() || ancillary.contains(chunk_type))
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// SHA-256 over type, length and data of every covered chunk, in file order.
+pub fn digest(png: &Png, ancillary: &[ChunkType]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for index in covered_chunks(png, ancillary) {
+        let chunk = &png.chunks()[index];
+        hasher.update(chunk.chunk_type().bytes());
+        hasher.update(chunk.length().to_be_bytes());
+        hasher.update(chunk.data());
+    }
+    hasher.finalize().into()
+}
+
+/// Signs `png` with `key` and stores the result in a signature chunk before
+/// IEND, replacing any earlier signature.
+pub fn sign(png: &mut Png, key: &SigningKey, ancillary: &[ChunkType]) -> Result<()> {
+    if ancillary.len() > u8::MAX as usize {
+        return Err(SignatureError::TooManyChunkTypes(ancillary.len()).into());
+    }
+    let name = ChunkType::try_from(CHUNK_TYPE).unwrap().to_string();
+    while png.remove_chunk(&name).is_ok() {}
+
+    let mut signature_chunk = SignatureChunk {
+        public_key: key.verifying_key().to_bytes(),
+        ancillary: ancillary.to_vec(),
+        signature: [0; SIGNATURE_LENGTH]
+    };
+    let message = signed_message(&signature_chunk, png);
+    signature_chunk.signature = key.sign(&message).to_bytes();
+    png.append_chunk(signature_chunk.to_chunk());
+    Ok(())
+}
+
+/// Checks the signature chunk of `png` against `key` and returns the indices
+/// of the chunks it covers.
+pub fn verify(png: &Png, key: &VerifyingKey) -> Result<Vec<usize>> {
+    let chunk = png.chunks()
+        .iter()
+        .find(|chunk| ordering::is(chunk.chunk_type(), &CHUNK_TYPE))
+        .ok_or(SignatureError::Missing)?;
+    let signature_chunk = SignatureChunk::try_from(chunk)?;
+    if signature_chunk.public_key != key.to_bytes() {
+        return Err(SignatureError::KeyMismatch.into());
+    }
+
+    let message = signed_message(&signature_chunk, png);
+    key.verify(&message, &Signature::from_bytes(&signature_chunk.signature))
+        .map_err(|_| SignatureError::Invalid)?;
+    Ok(covered_chunks(png, &signature_chunk.ancillary))
+}
+
+fn signed_message(signature_chunk: &SignatureChunk, png: &Png) -> Vec<u8> {
+    let mut message = signature_chunk.signed_prefix();
+    message.extend_from_slice(&digest(png, &signature_chunk.ancillary));
+    message
+}
+
+/// Reads an Ed25519 key file holding either the 32 raw key bytes or them
+/// written as 64 hex digits.
+pub fn read_key_file<P: AsRef<Path>>(path: P) -> Result<[u8; KEY_LENGTH]> {
+    let contents = fs::read(path)?;
+    if contents.len() == KEY_LENGTH {
+        let mut key = [0; KEY_LENGTH];
+        key.copy_from_slice(&contents);
+        return Ok(key);
+    }
+    let text = std::str::from_utf8(&contents).map_err(|_| SignatureError::InvalidKeyFile)?.trim();
+    from_hex(text).ok_or_else(|| SignatureError::InvalidKeyFile.into())
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<[u8; KEY_LENGTH]> {
+    if text.len() != KEY_LENGTH * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut key = [0; KEY_LENGTH];
+    for (index, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Parses a comma separated list of chunk types, as given to `sign --include`.
+pub fn parse_chunk_types(list: &str) -> Result<Vec<ChunkType>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(ChunkType::from_str)
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    Missing,
+    Malformed,
+    UnsupportedVersion(u8),
+    TooManyChunkTypes(usize),
+    InvalidKeyFile,
+    InvalidKey,
+    KeyMismatch,
+    Invalid
+}
+
+impl std::error::Error for SignatureError {}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "Png is not signed"),
+            SignatureError::Malformed => write!(f, "Signature chunk is malformed"),
+            SignatureError::UnsupportedVersion(version) => write!(f, "Unsupported signature version {}", version),
+            SignatureError::TooManyChunkTypes(count) => write!(f, "Can't cover {} ancillary chunk types, 255 at most", count),
+            SignatureError::InvalidKeyFile => write!(f, "Key file must hold 32 bytes, raw or as 64 hex digits"),
+            SignatureError::InvalidKey => write!(f, "Key is not a valid Ed25519 public key"),
+            SignatureError::KeyMismatch => write!(f, "Png was signed with a different key"),
+            SignatureError::Invalid => write!(f, "Signature doesn't match, the png was changed after signing")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("tEXt", b"Author\0pipeline"),
+            chunk("IDAT", b"pixels"),
+            chunk("ruSt", b"hidden"),
+            chunk("IEND", b""),
+        ])
+    }
+
+    fn testing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; KEY_LENGTH])
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let mut png = testing_png();
+        sign(&mut png, &testing_key(), &[]).unwrap();
+
+        assert_eq!(png.chunks().len(), 6);
+        assert_eq!(png.chunks()[4].chunk_type().to_string(), "siGN");
+        assert_eq!(verify(&png, &testing_key().verifying_key()).unwrap(), vec![0, 2, 5]);
+    }
+
+    #[test]
+    fn test_ancillary_chunks() {
+        let mut png = testing_png();
+        let text = ChunkType::from_str("tEXt").unwrap();
+        sign(&mut png, &testing_key(), &[text]).unwrap();
+        assert_eq!(verify(&png, &testing_key().verifying_key()).unwrap(), vec![0, 1, 2, 5]);
+
+        // uncovered chunks can change, covered ones can't
+        png.append_chunk(chunk("zzZz", b"added later"));
+        assert!(verify(&png, &testing_key().verifying_key()).is_ok());
+        png.remove_chunk("tEXt").unwrap();
+        assert!(matches!(verify(&png, &testing_key().verifying_key()), Err(Error::Signature(SignatureError::Invalid))));
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let mut png = testing_png();
+        sign(&mut png, &testing_key(), &[]).unwrap();
+        let mut chunks = png.chunks().to_vec();
+        chunks[2] = chunk("IDAT", b"pixelz");
+        let tampered = Png::from_chunks(chunks);
+
+        assert!(matches!(verify(&tampered, &testing_key().verifying_key()), Err(Error::Signature(SignatureError::Invalid))));
+    }
+
+    #[test]
+    fn test_wrong_key_and_unsigned() {
+        let mut png = testing_png();
+        assert!(matches!(verify(&png, &testing_key().verifying_key()), Err(Error::Signature(SignatureError::Missing))));
+
+        sign(&mut png, &testing_key(), &[]).unwrap();
+        let other = SigningKey::from_bytes(&[8; KEY_LENGTH]).verifying_key();
+        assert!(matches!(verify(&png, &other), Err(Error::Signature(SignatureError::KeyMismatch))));
+    }
+
+    #[test]
+    fn test_resign_replaces_signature() {
+        let mut png = testing_png();
+        sign(&mut png, &testing_key(), &[]).unwrap();
+        sign(&mut png, &testing_key(), &[]).unwrap();
+
+        assert_eq!(png.chunks_by_type("siGN").len(), 1);
+        assert!(verify(&png, &testing_key().verifying_key()).is_ok());
+    }
+
+    #[test]
+    fn test_signature_chunk_round_trip() {
+        let signature_chunk = SignatureChunk {
+            public_key: [1; KEY_LENGTH],
+            ancillary: parse_chunk_types("tEXt, iTXt").unwrap(),
+            signature: [2; SIGNATURE_LENGTH]
+        };
+        assert_eq!(SignatureChunk::try_from(&signature_chunk.to_chunk()).unwrap(), signature_chunk);
+        assert!(SignatureChunk::try_from(&chunk("siGN", &[VERSION; 40])).is_err());
+    }
+
+    #[test]
+    fn test_key_files() {
+        let path = std::env::temp_dir().join(format!("pngme-key-{}", std::process::id()));
+        fs::write(&path, [9; KEY_LENGTH]).unwrap();
+        assert_eq!(read_key_file(&path).unwrap(), [9; KEY_LENGTH]);
+
+        fs::write(&path, format!("{}\n", to_hex(&[0xab; KEY_LENGTH]))).unwrap();
+        assert_eq!(read_key_file(&path).unwrap(), [0xab; KEY_LENGTH]);
+
+        fs::write(&path, "not a key").unwrap();
+        assert!(read_key_file(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
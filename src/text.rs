@@ -0,0 +1,384 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::compression::{self, CompressionOptions};
+use crate::ordering;
+use crate::{Error, Result};
+
+// keywords are 1 to 79 latin-1 bytes
+const MAX_KEYWORD_LENGTH: usize = 79;
+
+/// An uncompressed `tEXt` chunk, keyword and text are latin-1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub keyword: String,
+    pub text: String
+}
+
+/// A `zTXt` chunk, the latin-1 text is stored zlib compressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedTextChunk {
+    pub keyword: String,
+    pub text: String
+}
+
+/// An `iTXt` chunk: utf-8 text, optionally compressed, with a language tag and
+/// the keyword translated into that language.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternationalTextChunk {
+    pub keyword: String,
+    pub compressed: bool,
+    pub language_tag: String,
+    pub translated_keyword: String,
+    pub text: String
+}
+
+/// Any of the three textual chunk kinds, as returned by `Png::text_entries`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextEntry {
+    Text(TextChunk),
+    Compressed(CompressedTextChunk),
+    International(InternationalTextChunk)
+}
+
+impl TextEntry {
+    /// Parses `chunk` if it is one of tEXt, zTXt or iTXt.
+    pub fn from_chunk(chunk: &Chunk) -> Option<Result<Self>> {
+        let chunk_type = chunk.chunk_type();
+        if ordering::is(chunk_type, &TextChunk::CHUNK_TYPE) {
+            Some(TextChunk::try_from(chunk).map(TextEntry::Text))
+        } else if ordering::is(chunk_type, &CompressedTextChunk::CHUNK_TYPE) {
+            Some(CompressedTextChunk::try_from(chunk).map(TextEntry::Compressed))
+        } else if ordering::is(chunk_type, &InternationalTextChunk::CHUNK_TYPE) {
+            Some(InternationalTextChunk::try_from(chunk).map(TextEntry::International))
+        } else {
+            None
+        }
+    }
+
+    pub fn keyword(&self) -> &str {
+        match self {
+            TextEntry::Text(entry) => &entry.keyword,
+            TextEntry::Compressed(entry) => &entry.keyword,
+            TextEntry::International(entry) => &entry.keyword
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            TextEntry::Text(entry) => &entry.text,
+            TextEntry::Compressed(entry) => &entry.text,
+            TextEntry::International(entry) => &entry.text
+        }
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        match self {
+            TextEntry::Text(entry) => entry.to_chunk(),
+            TextEntry::Compressed(entry) => entry.to_chunk(),
+            TextEntry::International(entry) => entry.to_chunk()
+        }
+    }
+}
+
+impl TryFrom<&Chunk> for TextChunk {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        check_type(chunk, &Self::CHUNK_TYPE)?;
+        let (keyword, text) = split_keyword(chunk.data())?;
+        Ok(TextChunk { keyword, text: from_latin1(text) })
+    }
+}
+
+impl TextChunk {
+    pub const CHUNK_TYPE: [u8; 4] = *b"tEXt";
+
+    /// Checks that both strings can be stored as latin-1 and the keyword is valid.
+    pub fn new(keyword: &str, text: &str) -> Result<Self> {
+        check_keyword(keyword)?;
+        to_latin1(text)?;
+        Ok(TextChunk { keyword: keyword.to_string(), text: text.to_string() })
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = keyword_bytes(&self.keyword);
+        data.extend(to_latin1(&self.text).expect("checked by the constructor"));
+        Chunk::new(ChunkType::from_str("tEXt").unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for CompressedTextChunk {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        check_type(chunk, &Self::CHUNK_TYPE)?;
+        let (keyword, rest) = split_keyword(chunk.data())?;
+        let (&method, compressed) = rest.split_first().ok_or(TextError::Truncated)?;
+        check_compression_method(method)?;
+        Ok(CompressedTextChunk { keyword, text: from_latin1(&inflate(compressed)?) })
+    }
+}
+
+impl CompressedTextChunk {
+    pub const CHUNK_TYPE: [u8; 4] = *b"zTXt";
+
+    /// Checks that both strings can be stored as latin-1 and the keyword is valid.
+    pub fn new(keyword: &str, text: &str) -> Result<Self> {
+        check_keyword(keyword)?;
+        to_latin1(text)?;
+        Ok(CompressedTextChunk { keyword: keyword.to_string(), text: text.to_string() })
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = keyword_bytes(&self.keyword);
+        data.push(0); // compression method, zlib is the only one
+        data.extend(deflate(&to_latin1(&self.text).expect("checked by the constructor")));
+        Chunk::new(ChunkType::from_str("zTXt").unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for InternationalTextChunk {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        check_type(chunk, &Self::CHUNK_TYPE)?;
+        let (keyword, rest) = split_keyword(chunk.data())?;
+        if rest.len() < 2 {
+            return Err(TextError::Truncated.into());
+        }
+        let compressed = match rest[0] {
+            0 => false,
+            1 => true,
+            flag => return Err(TextError::InvalidCompressionFlag(flag).into())
+        };
+        check_compression_method(rest[1])?;
+
+        let (language_tag, rest) = split_at_nul(&rest[2..])?;
+        let (translated_keyword, text) = split_at_nul(rest)?;
+        let text = if compressed { inflate(text)? } else { text.to_vec() };
+        Ok(InternationalTextChunk {
+            keyword,
+            compressed,
+            language_tag: String::from(std::str::from_utf8(language_tag)?),
+            translated_keyword: String::from(std::str::from_utf8(translated_keyword)?),
+            text: String::from_utf8(text).map_err(|error| error.utf8_error())?
+        })
+    }
+}
+
+impl InternationalTextChunk {
+    pub const CHUNK_TYPE: [u8; 4] = *b"iTXt";
+
+    /// Checks the keyword and that the language tag is plain ascii without NULs.
+    pub fn new(keyword: &str, language_tag: &str, translated_keyword: &str, text: &str, compressed: bool) -> Result<Self> {
+        check_keyword(keyword)?;
+        if !language_tag.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-') {
+            return Err(TextError::InvalidLanguageTag(language_tag.to_string()).into());
+        }
+        if translated_keyword.contains('\0') {
+            return Err(TextError::InvalidKeyword(translated_keyword.to_string()).into());
+        }
+        Ok(InternationalTextChunk {
+            keyword: keyword.to_string(),
+            compressed,
+            language_tag: language_tag.to_string(),
+            translated_keyword: translated_keyword.to_string(),
+            text: text.to_string()
+        })
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = keyword_bytes(&self.keyword);
+        data.push(self.compressed as u8);
+        data.push(0); // compression method
+        data.extend_from_slice(self.language_tag.as_bytes());
+        data.push(0);
+        data.extend_from_slice(self.translated_keyword.as_bytes());
+        data.push(0);
+        if self.compressed {
+            data.extend(deflate(self.text.as_bytes()));
+        } else {
+            data.extend_from_slice(self.text.as_bytes());
+        }
+        Chunk::new(ChunkType::from_str("iTXt").unwrap(), data)
+    }
+}
+
+fn check_type(chunk: &Chunk, expected: &[u8; 4]) -> Result<()> {
+    if !ordering::is(chunk.chunk_type(), expected) {
+        return Err(TextError::NotTextChunk(chunk.chunk_type().to_string()).into());
+    }
+    Ok(())
+}
+
+// the spec limits keywords to printable latin-1 without leading, trailing or double spaces
+fn check_keyword(keyword: &str) -> Result<()> {
+    let valid = !keyword.is_empty()
+        && keyword.chars().count() <= MAX_KEYWORD_LENGTH
+        && keyword.chars().all(|c| (' '..='~').contains(&c) || ('\u{a1}'..='\u{ff}').contains(&c))
+        && !keyword.starts_with(' ')
+        && !keyword.ends_with(' ')
+        && !keyword.contains("  ");
+    if !valid {
+        return Err(TextError::InvalidKeyword(keyword.to_string()).into());
+    }
+    Ok(())
+}
+
+// keyword followed by its NUL separator
+fn keyword_bytes(keyword: &str) -> Vec<u8> {
+    let mut bytes = to_latin1(keyword).expect("checked by the constructor");
+    bytes.push(0);
+    bytes
+}
+
+fn split_keyword(data: &[u8]) -> Result<(String, &[u8])> {
+    let (keyword, rest) = split_at_nul(data)?;
+    let keyword = from_latin1(keyword);
+    check_keyword(&keyword)?;
+    Ok((keyword, rest))
+}
+
+fn split_at_nul(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let nul = data.iter().position(|byte| *byte == 0).ok_or(TextError::MissingSeparator)?;
+    Ok((&data[..nul], &data[nul + 1..]))
+}
+
+fn check_compression_method(method: u8) -> Result<()> {
+    if method != 0 {
+        return Err(TextError::InvalidCompressionMethod(method).into());
+    }
+    Ok(())
+}
+
+// every latin-1 byte is the unicode code point with the same value
+fn from_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| *byte as char).collect()
+}
+
+fn to_latin1(text: &str) -> Result<Vec<u8>> {
+    text.chars()
+        .map(|c| u8::try_from(u32::from(c)).map_err(|_| TextError::NotLatin1(c).into()))
+        .collect()
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    compression::inflate(data, CompressionOptions::default().max_decompressed_size)
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    compression::deflate(data, compression::DEFAULT_LEVEL).expect("writing to a Vec never fails")
+}
+
+#[derive(Debug)]
+pub enum TextError {
+    NotTextChunk(String),
+    MissingSeparator,
+    Truncated,
+    InvalidKeyword(String),
+    InvalidLanguageTag(String),
+    NotLatin1(char),
+    InvalidCompressionFlag(u8),
+    InvalidCompressionMethod(u8)
+}
+
+impl std::error::Error for TextError {}
+
+impl Display for TextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TextError::NotTextChunk(chunk_type) => write!(f, "{} is not a text chunk", chunk_type),
+            TextError::MissingSeparator => write!(f, "Text chunk is missing a NUL separator"),
+            TextError::Truncated => write!(f, "Text chunk ends too early"),
+            TextError::InvalidKeyword(keyword) => write!(f, "Invalid text keyword {:?}", keyword),
+            TextError::InvalidLanguageTag(tag) => write!(f, "Invalid language tag {:?}", tag),
+            TextError::NotLatin1(c) => write!(f, "Character {:?} can't be stored as latin-1", c),
+            TextError::InvalidCompressionFlag(flag) => write!(f, "Invalid compression flag {}", flag),
+            TextError::InvalidCompressionMethod(method) => write!(f, "Unknown compression method {}", method)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_text_chunk_latin1() {
+        let parsed = TextChunk::try_from(&chunk("tEXt", b"Author\0Ren\xe9e")).unwrap();
+        assert_eq!(parsed.keyword, "Author");
+        assert_eq!(parsed.text, "Renée");
+
+        let built = TextChunk::new("Author", "Renée").unwrap().to_chunk();
+        assert_eq!(built.data(), b"Author\0Ren\xe9e");
+    }
+
+    #[test]
+    fn test_text_chunk_rejects_non_latin1() {
+        assert!(matches!(TextChunk::new("Title", "日本"), Err(Error::Text(TextError::NotLatin1('日')))));
+    }
+
+    #[test]
+    fn test_invalid_keywords() {
+        for keyword in ["", " lead", "trail ", "dou  ble", "tab\there", &"k".repeat(80)] {
+            assert!(TextChunk::new(keyword, "text").is_err(), "{:?}", keyword);
+        }
+        assert!(TextChunk::new(&"k".repeat(79), "text").is_ok());
+        assert!(TextChunk::try_from(&chunk("tEXt", b"no separator")).is_err());
+    }
+
+    #[test]
+    fn test_compressed_text_round_trip() {
+        let entry = CompressedTextChunk::new("Comment", "ça compresse bien bien bien bien").unwrap();
+        let chunk = entry.to_chunk();
+
+        assert_eq!(&chunk.data()[..9], b"Comment\0\0");
+        assert_eq!(CompressedTextChunk::try_from(&chunk).unwrap(), entry);
+    }
+
+    #[test]
+    fn test_compressed_text_bad_method() {
+        let mut data = CompressedTextChunk::new("Comment", "text").unwrap().to_chunk().data().to_vec();
+        data[8] = 1;
+        assert!(matches!(CompressedTextChunk::try_from(&chunk("zTXt", &data)),
+            Err(Error::Text(TextError::InvalidCompressionMethod(1)))));
+    }
+
+    #[test]
+    fn test_international_text_round_trip() {
+        for compressed in [false, true] {
+            let entry = InternationalTextChunk::new("Title", "ja", "タイトル", "日本語のテキスト", compressed).unwrap();
+            assert_eq!(InternationalTextChunk::try_from(&entry.to_chunk()).unwrap(), entry);
+        }
+    }
+
+    #[test]
+    fn test_international_text_fields() {
+        let parsed = InternationalTextChunk::try_from(&chunk("iTXt", b"Title\0\0\0en-GB\0Title\0Hello")).unwrap();
+        assert!(!parsed.compressed);
+        assert_eq!(parsed.language_tag, "en-GB");
+        assert_eq!(parsed.translated_keyword, "Title");
+        assert_eq!(parsed.text, "Hello");
+
+        assert!(InternationalTextChunk::try_from(&chunk("iTXt", b"Title\0\x02\0\0\0Hello")).is_err());
+        assert!(InternationalTextChunk::try_from(&chunk("iTXt", b"Title\0\0\0en")).is_err());
+    }
+
+    #[test]
+    fn test_text_entry_from_chunk() {
+        assert!(TextEntry::from_chunk(&chunk("ruSt", b"data")).is_none());
+
+        let entry = TextEntry::from_chunk(&chunk("tEXt", b"Author\0me")).unwrap().unwrap();
+        assert_eq!((entry.keyword(), entry.text()), ("Author", "me"));
+        assert!(matches!(TextChunk::try_from(&chunk("zTXt", b"Author\0me")),
+            Err(Error::Text(TextError::NotTextChunk(_)))));
+    }
+}
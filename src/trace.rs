@@ -0,0 +1,32 @@
+// Opt-in debug output for the parsers. Built only with `--features trace` and
+// printed to stderr only when `--verbose` is given, so stdout stays clean for
+// piping whatever the build.
+
+#[cfg(feature = "trace")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "trace")]
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "trace")]
+pub fn set_verbose(enabled: bool) {
+    VERBOSE.store(enabled, Ordering::Relaxed);
+}
+
+#[cfg(feature = "trace")]
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Writes a `key=value` style debug line to stderr when tracing is enabled.
+/// Compiles to nothing without the `trace` feature.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "trace")]
+        {
+            if $crate::trace::is_verbose() {
+                eprintln!("debug: {}", format_args!($($arg)*));
+            }
+        }
+    };
+}
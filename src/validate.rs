@@ -0,0 +1,390 @@
+use std::fmt::Display;
+
+use crate::chunk::Chunk;
+use crate::chunk_ref::ChunkRefs;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::ordering::{self, order_violations};
+use crate::png::{Png, PngFile};
+use crate::recover;
+
+// critical chunks this crate knows, any other critical chunk can't be decoded
+const KNOWN_CRITICAL: [&[u8; 4]; 4] = [b"IHDR", b"PLTE", b"IDAT", b"IEND"];
+// chunks the spec allows at most once
+const SINGLE: [&[u8; 4]; 15] = [
+    b"IHDR", b"PLTE", b"IEND", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB",
+    b"cICP", b"bKGD", b"hIST", b"tRNS", b"pHYs", b"tIME", b"eXIf"
+];
+// chunk lengths are limited to 2^31 - 1 by the spec
+const MAX_CHUNK_LENGTH: usize = i32::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error
+}
+
+/// One problem found by the validator, `offset` is the byte offset in the
+/// file of the chunk (or data) it is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub offset: usize,
+    pub chunk_type: Option<String>,
+    pub message: String
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error"
+        };
+        match &self.chunk_type {
+            Some(chunk_type) => write!(f, "{} at byte {} ({}): {}", severity, self.offset, chunk_type, self.message),
+            None => write!(f, "{} at byte {}: {}", severity, self.offset, self.message)
+        }
+    }
+}
+
+// what the checks need to know about a chunk, whether it is owned or borrowed
+struct Entry<'a> {
+    offset: usize,
+    chunk_type: &'a ChunkType,
+    data: &'a [u8]
+}
+
+/// Validates the chunks of `png`, see `Png::validate`.
+pub fn validate_png(png: &Png) -> Vec<Finding> {
+    let mut offset = Png::STANDARD_HEADER.len();
+    let entries: Vec<Entry> = png.chunks()
+        .iter()
+        .map(|chunk| {
+            let entry = Entry { offset, chunk_type: chunk.chunk_type(), data: chunk.data() };
+            offset += chunk.length() as usize + Chunk::METADATA_LENGTH;
+            entry
+        })
+        .collect();
+    validate_entries(&entries, true)
+}
+
+/// Validates a png file as raw bytes. On top of what `Png::validate` checks,
+/// this reports chunks that can't be parsed and data after IEND. Parsing goes
+/// on after a broken chunk from the next position that looks like a chunk.
+pub fn validate_bytes(bytes: &[u8]) -> Vec<Finding> {
+    let mut chunks = match ChunkRefs::new(bytes) {
+        Ok(chunks) => chunks,
+        Err(error) => return vec![finding(Severity::Error, 0, None, error.to_string())]
+    };
+
+    let mut findings = Vec::new();
+    let mut parsed = Vec::new();
+    let mut skipped = false;
+    loop {
+        let offset = chunks.offset();
+        match chunks.next() {
+            Some(Ok(chunk)) => {
+                let is_iend = ordering::is(chunk.chunk_type(), b"IEND");
+                parsed.push((offset, chunk));
+                if is_iend {
+                    break;
+                }
+            }
+            Some(Err(error)) => {
+                findings.push(finding(Severity::Error, offset, None, error.to_string()));
+                skipped = true;
+                chunks.skip_to(recover::resync(bytes, offset + 1));
+            }
+            None => break
+        }
+    }
+
+    let end = chunks.offset();
+    let ended_with_iend = parsed.last().is_some_and(|(_, chunk)| ordering::is(chunk.chunk_type(), b"IEND"));
+    if ended_with_iend && end < bytes.len() {
+        findings.push(finding(Severity::Warning, end, None,
+            format!("{} bytes of data after IEND", bytes.len() - end)));
+    }
+
+    let entries: Vec<Entry> = parsed
+        .iter()
+        .map(|(offset, chunk)| Entry { offset: *offset, chunk_type: chunk.chunk_type(), data: chunk.data() })
+        .collect();
+    // a chunk we couldn't read may well be the one that looks missing
+    findings.extend(validate_entries(&entries, !skipped));
+    findings.sort_by_key(|finding| finding.offset);
+    findings
+}
+
+fn validate_entries(entries: &[Entry], check_missing: bool) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        let chunk_type = entry.chunk_type;
+        if !chunk_type.bytes().iter().all(|byte| ChunkType::is_valid_byte(*byte)) {
+            error(&mut findings, entry, String::from("chunk type must be four ascii letters"));
+            continue;
+        }
+        if !chunk_type.is_reserved_bit_valid() {
+            error(&mut findings, entry, String::from("reserved bit is set, third letter must be uppercase"));
+        }
+        if chunk_type.is_critical() && !KNOWN_CRITICAL.iter().any(|name| ordering::is(chunk_type, name)) {
+            error(&mut findings, entry, String::from("unknown critical chunk, decoders must reject the file"));
+        }
+        if entry.data.len() > MAX_CHUNK_LENGTH {
+            error(&mut findings, entry, format!("chunk length {} is over the 2^31 - 1 limit", entry.data.len()));
+        }
+        let seen_before = entries[..index].iter().any(|other| other.chunk_type == chunk_type);
+        if seen_before && SINGLE.iter().any(|name| ordering::is(chunk_type, name)) {
+            error(&mut findings, entry, String::from("chunk may only appear once"));
+        }
+    }
+
+    let chunk_types: Vec<&ChunkType> = entries.iter().map(|entry| entry.chunk_type).collect();
+    for violation in order_violations(&chunk_types) {
+        error(&mut findings, &entries[violation.index], String::from(violation.reason));
+    }
+
+    let find = |name: &[u8; 4]| entries.iter().find(|entry| ordering::is(entry.chunk_type, name));
+    let end = entries.last().map_or(Png::STANDARD_HEADER.len(), |entry| {
+        entry.offset + entry.data.len() + Chunk::METADATA_LENGTH
+    });
+    let ihdr = match find(b"IHDR") {
+        Some(entry) => match Ihdr::from_data(entry.data) {
+            Ok(ihdr) => Some(ihdr),
+            Err(ihdr_error) => {
+                error(&mut findings, entry, ihdr_error.to_string());
+                None
+            }
+        },
+        None => {
+            if check_missing {
+                findings.push(finding(Severity::Error, Png::STANDARD_HEADER.len(), None, String::from("IHDR chunk is missing")));
+            }
+            None
+        }
+    };
+    if check_missing && find(b"IDAT").is_none() {
+        findings.push(finding(Severity::Error, end, None, String::from("IDAT chunk is missing")));
+    }
+    if check_missing && find(b"IEND").is_none() {
+        findings.push(finding(Severity::Error, end, None, String::from("IEND chunk is missing")));
+    }
+
+    if let Some(ihdr) = ihdr {
+        findings.extend(check_palette(&ihdr, find(b"PLTE"), end));
+    }
+
+    findings.sort_by_key(|finding| finding.offset);
+    findings
+}
+
+// the palette has to agree with the color type and bit depth from IHDR
+fn check_palette(ihdr: &Ihdr, plte: Option<&Entry>, end: usize) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let plte = match plte {
+        Some(plte) => plte,
+        None => {
+            if ihdr.color_type == ColorType::Indexed {
+                findings.push(finding(Severity::Error, end, None,
+                    String::from("PLTE chunk is required for indexed color")));
+            }
+            return findings;
+        }
+    };
+
+    match ihdr.color_type {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha =>
+            error(&mut findings, plte, String::from("PLTE is not allowed for grayscale images")),
+        _ => {
+            let length = plte.data.len();
+            if length == 0 || length % 3 != 0 {
+                error(&mut findings, plte, format!("PLTE length {} is not a non-zero multiple of 3", length));
+            }
+            let entries = length / 3;
+            let limit = if ihdr.color_type == ColorType::Indexed {
+                (1usize << ihdr.bit_depth).min(256)
+            } else {
+                256
+            };
+            if entries > limit {
+                error(&mut findings, plte, format!("PLTE has {} entries, at most {} allowed", entries, limit));
+            }
+        }
+    }
+    findings
+}
+
+fn error(findings: &mut Vec<Finding>, entry: &Entry, message: String) {
+    findings.push(finding(Severity::Error, entry.offset, Some(entry.chunk_type), message));
+}
+
+pub(crate) fn finding(severity: Severity, offset: usize, chunk_type: Option<&ChunkType>, message: String) -> Finding {
+    Finding {
+        severity,
+        offset,
+        chunk_type: chunk_type.map(|chunk_type| chunk_type.to_string()),
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::InterlaceMethod;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn ihdr_chunk(color_type: ColorType, bit_depth: u8) -> Chunk {
+        Ihdr {
+            width: 1,
+            height: 1,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: InterlaceMethod::None
+        }.to_chunk()
+    }
+
+    fn valid_chunks() -> Vec<Chunk> {
+        vec![
+            ihdr_chunk(ColorType::Rgb, 8),
+            chunk("IDAT", &[1, 2, 3]),
+            chunk("IEND", &[])
+        ]
+    }
+
+    fn messages(findings: &[Finding]) -> Vec<&str> {
+        findings.iter().map(|finding| finding.message.as_str()).collect()
+    }
+
+    #[test]
+    fn test_valid_png() {
+        assert!(Png::from_chunks(valid_chunks()).validate().is_empty());
+        assert!(validate_bytes(&Png::from_chunks(valid_chunks()).as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn test_missing_critical_chunks() {
+        let findings = Png::from_chunks(Vec::new()).validate();
+        assert_eq!(messages(&findings), vec![
+            "IHDR chunk is missing", "IDAT chunk is missing", "IEND chunk is missing"
+        ]);
+    }
+
+    #[test]
+    fn test_reports_every_problem_with_offsets() {
+        let mut chunks = valid_chunks();
+        chunks.insert(1, chunk("gAMA", &[0, 0, 177, 143]));
+        chunks.insert(2, chunk("gAMA", &[0, 0, 177, 143]));
+        chunks.insert(3, chunk("rust", b"reserved"));
+        chunks.insert(4, chunk("RUST", b"critical"));
+        let png = Png::from_chunks(chunks);
+        let findings = png.validate();
+
+        let gama_offset = 8 + 25 + 16;
+        assert_eq!(findings.len(), 3);
+        assert_eq!(findings[0].offset, gama_offset);
+        assert_eq!(findings[0].message, "chunk may only appear once");
+        assert_eq!(findings[1].chunk_type.as_deref(), Some("rust"));
+        assert_eq!(findings[2].chunk_type.as_deref(), Some("RUST"));
+        assert!(findings.iter().all(|finding| finding.severity == Severity::Error));
+    }
+
+    #[test]
+    fn test_order_findings() {
+        let mut chunks = valid_chunks();
+        chunks.push(chunk("IDAT", &[4]));
+        let findings = Png::from_chunks(chunks).validate();
+
+        assert_eq!(messages(&findings), vec!["IEND must be the last chunk", "IDAT chunks must be consecutive"]);
+    }
+
+    #[test]
+    fn test_palette_consistency() {
+        let png = Png::from_chunks(vec![
+            ihdr_chunk(ColorType::Indexed, 1),
+            chunk("IDAT", &[0]),
+            chunk("IEND", &[])
+        ]);
+        assert_eq!(messages(&png.validate()), vec!["PLTE chunk is required for indexed color"]);
+
+        let png = Png::from_chunks(vec![
+            ihdr_chunk(ColorType::Indexed, 1),
+            chunk("PLTE", &[0; 9]),
+            chunk("IDAT", &[0]),
+            chunk("IEND", &[])
+        ]);
+        assert_eq!(messages(&png.validate()), vec!["PLTE has 3 entries, at most 2 allowed"]);
+
+        let png = Png::from_chunks(vec![
+            ihdr_chunk(ColorType::Grayscale, 8),
+            chunk("PLTE", &[0; 4]),
+            chunk("IDAT", &[0]),
+            chunk("IEND", &[])
+        ]);
+        assert_eq!(messages(&png.validate()), vec!["PLTE is not allowed for grayscale images"]);
+    }
+
+    #[test]
+    fn test_invalid_ihdr() {
+        let mut chunks = valid_chunks();
+        chunks[0] = chunk("IHDR", &[0; 13]);
+        let findings = Png::from_chunks(chunks).validate();
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].offset, 8);
+    }
+
+    #[test]
+    fn test_validate_bytes_trailing_data() {
+        let mut bytes = Png::from_chunks(valid_chunks()).as_bytes();
+        let end = bytes.len();
+        bytes.extend_from_slice(b"hidden");
+        let findings = validate_bytes(&bytes);
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert_eq!(findings[0].offset, end);
+    }
+
+    #[test]
+    fn test_validate_bytes_broken_chunk() {
+        let bytes = Png::from_chunks(valid_chunks()).as_bytes();
+        let findings = validate_bytes(&bytes[..bytes.len() - 3]);
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].offset, bytes.len() - 12);
+        assert_eq!(validate_bytes(b"not a png").len(), 1);
+    }
+
+    #[test]
+    fn test_validate_bytes_goes_on_after_bad_crc() {
+        let mut chunks = valid_chunks();
+        chunks.insert(1, chunk("tEXt", b"Comment\0hello"));
+        let mut bytes = Png::from_chunks(chunks).as_bytes();
+        let crc_offset = 8 + 25 + 8 + 13;
+        bytes[crc_offset] ^= 0xff;
+        bytes.extend_from_slice(b"hidden");
+        let findings = validate_bytes(&bytes);
+
+        assert_eq!(findings.len(), 2);
+        assert_eq!((findings[0].severity, findings[0].offset), (Severity::Error, 8 + 25));
+        assert_eq!(findings[1].message, "6 bytes of data after IEND");
+    }
+
+    #[test]
+    fn test_validate_invalid_chunk_type() {
+        let mut chunks = valid_chunks();
+        chunks.insert(1, Chunk::new(ChunkType::try_from([b'r', b'u', b'S', b'1']).unwrap(), Vec::new()));
+        let findings = Png::from_chunks(chunks).validate();
+
+        assert_eq!(messages(&findings), vec!["chunk type must be four ascii letters"]);
+        assert!(ChunkType::from_str("ruS1").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::io::Write;
+
+use crate::chunk::Chunk;
+use crate::png::{Png, PngFile};
+use crate::Result;
+
+/// Writes a png straight to any `Write`, one `Chunk` at a time, without
+/// building the file in memory first.
+pub struct PngWriter<W: Write> {
+    writer: W
+}
+
+impl<W: Write> PngWriter<W> {
+    /// Writes the png signature, the chunks follow with `write_chunk`.
+    pub fn new(mut writer: W) -> Result<Self> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+        Ok(PngWriter { writer })
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        chunk.write_to(&mut self.writer)
+    }
+
+    /// Flushes the sink and hands it back.
+    pub fn finish(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_chunks() -> Vec<Chunk> {
+        vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"I am the first chunk".to_vec()),
+            Chunk::new(ChunkType::from_str("LASt").unwrap(), b"I am the last chunk".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn test_writes_signature_and_chunks() {
+        let chunks = testing_chunks();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        for chunk in &chunks {
+            writer.write_chunk(chunk).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        let expected: Vec<u8> = Png::STANDARD_HEADER
+            .iter()
+            .copied()
+            .chain(chunks.iter().flat_map(|chunk| chunk.as_bytes()))
+            .collect();
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn test_empty_png_is_just_the_signature() {
+        let bytes = PngWriter::new(Vec::new()).unwrap().finish().unwrap();
+        assert_eq!(bytes, Png::STANDARD_HEADER);
+    }
+}